@@ -0,0 +1,321 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+//! Config file describing the mice to look for
+use crate::format::Icons;
+use crate::mouse;
+use crate::thresholds::Thresholds;
+use hex::FromHex;
+use hidapi::DeviceInfo;
+use linked_hash_map::LinkedHashMap;
+use std::ffi::CString;
+use std::num::NonZeroU64;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+/// Contents of the config file
+#[derive(Debug, serde::Deserialize)]
+pub struct Config {
+    /// Glyphs used in human readable output
+    #[serde(default)]
+    pub icons: Icons,
+    /// Battery levels used for styling output and notifications
+    #[serde(default)]
+    pub thresholds: Thresholds,
+    /// Every other table is a mouse profile, kept in the order they were written
+    #[serde(flatten, deserialize_with = "deserialize_profiles")]
+    pub profiles: LinkedHashMap<String, MouseProfile>,
+}
+
+/// Profile describing a mouse
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MouseProfile {
+    /// Model name of the mouse
+    pub model: String,
+    /// Product id
+    #[serde(deserialize_with = "deserialize_id")]
+    pub product: u16,
+    /// Vendor id
+    #[serde(deserialize_with = "deserialize_id")]
+    pub vendor: u16,
+    /// Which HID interface of the device to talk to
+    #[serde(flatten)]
+    pub interface: Interface,
+    /// Serial number of the device, to tell identical mice apart
+    pub serial: Option<String>,
+    /// Read the battery from a feature report when the mouse doesn't answer the interrupt read,
+    /// which some Aerox 9 firmware revisions need
+    #[serde(default)]
+    pub feature_report_fallback: bool,
+    /// Advanced: offset of the battery byte in a response, overriding the model's, for bringing
+    /// up firmware variants of the Aerox 9
+    pub response_offset: Option<usize>,
+    /// Advanced: report id written before each request instead of 0, or `"none"` to write
+    /// requests bare, for bringing up devices with the Aerox drivers
+    pub report_id: Option<ReportId>,
+    /// Advanced: bit of the battery byte set while charging, overriding the model's, for
+    /// bringing up variants of the Aerox 9
+    pub charging_flag: Option<u8>,
+    /// Advanced: bit of the request asking the receiver, overriding the model's, for bringing
+    /// up variants of the Aerox 9 Wireless
+    pub wireless_flag: Option<u8>,
+    /// Seconds between battery checks, overriding `--interval`. Zero is rejected since it would
+    /// read the mouse back to back
+    pub interval: Option<NonZeroU64>,
+    /// Command run when the mouse starts charging, with the percentage appended
+    pub on_charge_start: Option<Vec<String>>,
+    /// Command run when the mouse stops charging, with the percentage appended
+    pub on_charge_stop: Option<Vec<String>>,
+}
+
+/// How to pick the HID interface that reports the battery
+#[derive(Debug, serde::Deserialize)]
+#[serde(try_from = "RawInterface")]
+pub enum Interface {
+    /// USB interface numbers, most preferred first
+    Endpoint(Vec<i32>),
+    /// HID usage page and usage of the interface
+    Usage { usage_page: u16, usage: u16 },
+    /// Device node of the interface, e.g. `/dev/hidraw3`
+    Path(CString),
+}
+impl Interface {
+    /// How preferred a matching device is, lower is better, or `None` if it doesn't match
+    pub fn rank(&self, device: &DeviceInfo) -> Option<usize> {
+        match *self {
+            Interface::Endpoint(ref endpoints) => endpoints
+                .iter()
+                .position(|&endpoint| device.interface_number() == endpoint),
+            Interface::Usage { usage_page, usage } => {
+                (device.usage_page() == usage_page && device.usage() == usage).then_some(0)
+            }
+            Interface::Path(ref path) => (device.path() == path.as_c_str()).then_some(0),
+        }
+    }
+}
+/// One interface number or a list of them to try in order
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Endpoints {
+    One(i32),
+    Many(Vec<i32>),
+}
+/// A report id, or `"none"` for no report id at all
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ReportId {
+    Id(u8),
+    /// `"none"`
+    Keyword(NoReportId),
+}
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoReportId {
+    None,
+}
+/// Interface fields as they appear in a profile
+#[derive(serde::Deserialize)]
+struct RawInterface {
+    endpoint: Option<Endpoints>,
+    usage_page: Option<u16>,
+    usage: Option<u16>,
+    path: Option<PathBuf>,
+}
+impl TryFrom<RawInterface> for Interface {
+    type Error = InterfaceError;
+
+    fn try_from(raw: RawInterface) -> Result<Self, Self::Error> {
+        if let Some(path) = raw.path {
+            if raw.endpoint.is_some() || raw.usage_page.is_some() || raw.usage.is_some() {
+                return Err(InterfaceError::Conflicting);
+            }
+            return CString::new(path.into_os_string().into_vec())
+                .map(Interface::Path)
+                .map_err(|_| InterfaceError::PathNul);
+        }
+        match (raw.endpoint, raw.usage_page, raw.usage) {
+            (Some(Endpoints::One(endpoint)), None, None) => Ok(Interface::Endpoint(vec![endpoint])),
+            (Some(Endpoints::Many(endpoints)), None, None) if endpoints.is_empty() => {
+                Err(InterfaceError::NoEndpoints)
+            }
+            (Some(Endpoints::Many(endpoints)), None, None) => Ok(Interface::Endpoint(endpoints)),
+            (None, Some(usage_page), Some(usage)) => Ok(Interface::Usage { usage_page, usage }),
+            (None, None, None) => Err(InterfaceError::Missing),
+            (Some(_), _, _) => Err(InterfaceError::Conflicting),
+            (None, _, _) => Err(InterfaceError::IncompleteUsage),
+        }
+    }
+}
+#[derive(Debug, thiserror::Error)]
+pub enum InterfaceError {
+    #[error("profile must set one of endpoint, usage_page and usage, or path")]
+    Missing,
+    #[error("profile must set only one of endpoint, usage_page/usage and path")]
+    Conflicting,
+    #[error("endpoint must list at least one interface")]
+    NoEndpoints,
+    #[error("path must not contain a NUL byte")]
+    PathNul,
+    #[error("usage_page and usage must be set together")]
+    IncompleteUsage,
+}
+impl MouseProfile {
+    /// Settings passed to the driver of this profile
+    pub fn driver_options(&self, read_timeout: i32) -> mouse::Options {
+        mouse::Options {
+            read_timeout,
+            feature_report_fallback: self.feature_report_fallback,
+            response_offset: self.response_offset,
+            report_id: match self.report_id {
+                None => mouse::DEFAULT_REPORT_ID,
+                Some(ReportId::Id(id)) => Some(id),
+                Some(ReportId::Keyword(NoReportId::None)) => None,
+            },
+            charging_flag: self.charging_flag,
+            wireless_flag: self.wireless_flag,
+        }
+    }
+
+    /// Whether a HID device is the interface of the mouse this profile describes
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        self.rank(device).is_some()
+    }
+
+    /// How preferred a matching interface is, lower is better, or `None` if it doesn't match
+    pub fn rank(&self, device: &DeviceInfo) -> Option<usize> {
+        if device.vendor_id() != self.vendor
+            || device.product_id() != self.product
+            || self
+                .serial
+                .as_deref()
+                .is_some_and(|serial| device.serial_number() != Some(serial))
+        {
+            return None;
+        }
+        self.interface.rank(device)
+    }
+}
+
+/// Deserializes the mouse profiles one at a time, naming the profile in errors
+///
+/// Errors inside a flattened map have no useful position, so the name is the only way to tell
+/// which table is wrong.
+fn deserialize_profiles<'de, D>(
+    deserializer: D,
+) -> Result<LinkedHashMap<String, MouseProfile>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::{Deserialize, Error};
+    LinkedHashMap::<String, toml::Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, profile)| {
+            let profile = profile
+                .try_into()
+                .map_err(|err| D::Error::custom(format!("mouse profile {name:?}: {err}")))?;
+            Ok((name, profile))
+        })
+        .collect()
+}
+
+/// Deserializes a USB vendor or product id
+///
+/// Strings are 4 hex digits, as printed by `lsusb`, while bare numbers are always decimal,
+/// as printed by `lsusb -v`. This means `product = 1971` is product 0x07b3, not 0x1971.
+fn deserialize_id<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct IdVisitor;
+    impl<'de> serde::de::Visitor<'de> for IdVisitor {
+        type Value = u16;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a string of 4 hex digits or a decimal integer")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<u16, E> {
+            let bytes = <[u8; 2]>::from_hex(v).map_err(E::custom)?;
+            Ok(u16::from_be_bytes(bytes))
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<u16, E> {
+            u16::try_from(v).map_err(|_| E::invalid_value(serde::de::Unexpected::Signed(v), &self))
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<u16, E> {
+            u16::try_from(v)
+                .map_err(|_| E::invalid_value(serde::de::Unexpected::Unsigned(v), &self))
+        }
+    }
+    deserializer.deserialize_any(IdVisitor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Ids {
+        #[serde(deserialize_with = "deserialize_id")]
+        product: u16,
+    }
+
+    #[test]
+    fn hex_and_decimal_ids() {
+        let hex: Ids = toml::from_str(r#"product = "1971""#).unwrap();
+        assert_eq!(hex.product, 0x1971);
+        let decimal: Ids = toml::from_str("product = 6513").unwrap();
+        assert_eq!(decimal.product, 0x1971);
+        assert!(toml::from_str::<Ids>("product = 65536").is_err());
+        assert!(toml::from_str::<Ids>(r#"product = "197""#).is_err());
+    }
+
+    #[test]
+    fn endpoints() {
+        let profile = |endpoint| {
+            toml::from_str::<MouseProfile>(&format!(
+                "model = \"m\"\nvendor = \"1038\"\nproduct = \"1858\"\nendpoint = {endpoint}"
+            ))
+            .map(|profile| profile.interface)
+        };
+        assert!(matches!(profile("3"), Ok(Interface::Endpoint(endpoints)) if endpoints == [3]));
+        assert!(
+            matches!(profile("[3, 4]"), Ok(Interface::Endpoint(endpoints)) if endpoints == [3, 4])
+        );
+        assert!(profile("[]").is_err());
+        assert!(profile("\"3\"").is_err());
+    }
+
+    #[test]
+    fn interval() {
+        let profile = |interval| {
+            toml::from_str::<MouseProfile>(&format!(
+                "model = \"m\"\nvendor = \"1038\"\nproduct = \"1858\"\nendpoint = 3\n\
+                 interval = {interval}"
+            ))
+            .map(|profile| profile.interval)
+        };
+        assert_eq!(profile("60").unwrap(), NonZeroU64::new(60));
+        assert!(profile("0").is_err());
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let err = toml::from_str::<Config>(
+            "[wireless]\nmodel = \"m\"\nvendor = \"1038\"\nproduct = \"1858\"\nendpoint = 3\n\
+             intreval = 60",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("\"wireless\""), "{err}");
+        assert!(err.contains("intreval"), "{err}");
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use nezumi::mouse::BatteryStatus;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Header row written to a new log file
+const HEADER: &str = "timestamp,model,percent,charging";
+
+/// Appends battery readings to a CSV file
+#[derive(Debug)]
+pub struct CsvLog {
+    file: File,
+}
+
+impl CsvLog {
+    /// Opens a log file for appending, writing the header row if the file is new
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{HEADER}")?;
+        }
+        Ok(CsvLog { file })
+    }
+
+    /// Appends a reading, timestamped in seconds since the Unix epoch
+    pub fn write(&mut self, model: &str, status: &BatteryStatus) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        writeln!(
+            self.file,
+            "{timestamp},{model},{},{}",
+            status.percent, status.is_charging
+        )
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use nezumi::mouse::BatteryStatus;
+use nezumi::thresholds::Level;
+use tokio::sync::mpsc;
+use tracing::error;
+use zbus::Connection;
+
+/// Well known name claimed on the session bus
+const BUS_NAME: &str = "space.witchof.Nezumi";
+/// Each mouse is exported below this path, under its profile name
+const PATH_PREFIX: &str = "/space/witchof/Nezumi/mouse";
+/// Device states as in `org.freedesktop.UPower.Device`
+const STATE_CHARGING: u32 = 1;
+const STATE_DISCHARGING: u32 = 2;
+const STATE_FULLY_CHARGED: u32 = 4;
+
+/// Battery of a single mouse, with properties named after UPower's
+struct Battery {
+    percentage: f64,
+    state: u32,
+    model: String,
+    stale: bool,
+}
+
+#[zbus::interface(name = "space.witchof.Nezumi.Battery")]
+impl Battery {
+    /// Battery percentage from 0 to 100
+    #[zbus(property)]
+    fn percentage(&self) -> f64 {
+        self.percentage
+    }
+
+    /// 1 while charging, 2 while discharging and 4 once at the full threshold
+    #[zbus(property)]
+    fn state(&self) -> u32 {
+        self.state
+    }
+
+    /// Model name of the mouse
+    #[zbus(property)]
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    /// Whether this is the last reading of a mouse that stopped answering, older than
+    /// `--stale-after`
+    #[zbus(property)]
+    fn stale(&self) -> bool {
+        self.stale
+    }
+}
+
+impl Battery {
+    fn new(model: String, status: &BatteryStatus, level: Level, stale: bool) -> Self {
+        Battery {
+            percentage: f64::from(status.percent),
+            state: match level {
+                Level::Full => STATE_FULLY_CHARGED,
+                Level::Charging => STATE_CHARGING,
+                Level::Critical | Level::Low | Level::Normal => STATE_DISCHARGING,
+            },
+            model,
+            stale,
+        }
+    }
+}
+
+/// Change to the exported mice, applied by the task serving the bus
+enum Update {
+    Set {
+        name: String,
+        model: String,
+        status: BatteryStatus,
+        level: Level,
+        stale: bool,
+    },
+    Remove(String),
+}
+
+/// Exports the battery of each mouse on the session bus
+pub struct Service {
+    updates: mpsc::UnboundedSender<Update>,
+}
+
+impl Service {
+    /// Connects to the session bus and claims [`BUS_NAME`]
+    pub async fn start() -> zbus::Result<Self> {
+        let connection = zbus::connection::Builder::session()?
+            .name(BUS_NAME)?
+            .build()
+            .await?;
+        let (updates, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(serve(connection, receiver));
+        Ok(Service { updates })
+    }
+
+    /// Exports the latest battery status of a mouse, emitting `PropertiesChanged` on changes
+    ///
+    /// Its state follows the level the thresholds put the status at
+    pub fn set(&self, name: &str, model: &str, status: BatteryStatus, level: Level, stale: bool) {
+        self.send(Update::Set {
+            name: name.to_owned(),
+            model: model.to_owned(),
+            status,
+            level,
+            stale,
+        });
+    }
+
+    /// Stops exporting a mouse whose battery is unknown
+    pub fn remove(&self, name: &str) {
+        self.send(Update::Remove(name.to_owned()));
+    }
+
+    fn send(&self, update: Update) {
+        if self.updates.send(update).is_err() {
+            error!("D-Bus service has stopped");
+        }
+    }
+}
+
+/// Applies updates to the exported objects as they arrive
+async fn serve(connection: Connection, mut updates: mpsc::UnboundedReceiver<Update>) {
+    while let Some(update) = updates.recv().await {
+        if let Err(err) = apply(&connection, update).await {
+            error!("Error updating D-Bus battery: {err}");
+        }
+    }
+}
+
+async fn apply(connection: &Connection, update: Update) -> zbus::Result<()> {
+    let object_server = connection.object_server();
+    match update {
+        Update::Set {
+            name,
+            model,
+            status,
+            level,
+            stale,
+        } => {
+            let path = object_path(&name);
+            let battery = Battery::new(model, &status, level, stale);
+            let iface_ref = match object_server.interface::<_, Battery>(path.as_str()).await {
+                Ok(iface_ref) => iface_ref,
+                Err(zbus::Error::InterfaceNotFound) => {
+                    object_server.at(path, battery).await?;
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            };
+            let mut iface = iface_ref.get_mut().await;
+            if iface.percentage != battery.percentage {
+                iface.percentage = battery.percentage;
+                iface.percentage_changed(iface_ref.signal_emitter()).await?;
+            }
+            if iface.state != battery.state {
+                iface.state = battery.state;
+                iface.state_changed(iface_ref.signal_emitter()).await?;
+            }
+            if iface.stale != battery.stale {
+                iface.stale = battery.stale;
+                iface.stale_changed(iface_ref.signal_emitter()).await?;
+            }
+            Ok(())
+        }
+        Update::Remove(name) => {
+            match object_server.remove::<Battery, _>(object_path(&name)).await {
+                Ok(_) | Err(zbus::Error::InterfaceNotFound) => Ok(()),
+                Err(err) => Err(err),
+            }
+        }
+    }
+}
+
+/// Object path of a mouse, with anything D-Bus doesn't allow in a path replaced by `_`
+fn object_path(name: &str) -> String {
+    let element: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{PATH_PREFIX}/{element}")
+}
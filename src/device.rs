@@ -0,0 +1,398 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+//! Finding and opening the mice of a config among the connected HID devices
+use crate::config::{Config, Interface, MouseProfile};
+use crate::mouse::{self, Mouse};
+use crate::poll::OpenMouse;
+use hidapi::{HidApi, HidDevice};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::time::{self, Duration};
+use tracing::{error, info, instrument, warn, Span};
+
+/// The interface of a profile found among the connected devices
+pub struct Candidate<'a> {
+    /// Name of the profile
+    pub name: &'a str,
+    pub profile: &'a MouseProfile,
+    /// Device node of the interface
+    pub path: CString,
+    /// Serial number the device list had for the interface, if any
+    pub serial: Option<String>,
+}
+impl Candidate<'_> {
+    /// The device node the interface was found on
+    pub fn node(&self) -> Node {
+        Node {
+            path: self.path.clone(),
+            serial: self.serial.clone(),
+        }
+    }
+}
+
+/// Device node a mouse was opened on, kept so it can be opened again without a rescan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub path: CString,
+    /// Serial number the device list had for the interface, if any
+    pub serial: Option<String>,
+}
+
+/// Whether a device node is still there, even if the device list hasn't been refreshed
+pub fn node_exists(path: &CStr) -> bool {
+    Path::new(OsStr::from_bytes(path.to_bytes())).exists()
+}
+
+/// Whether opening a device node failed because we may not access it
+///
+/// hidapi only gives us a message, so this tries opening the node itself
+fn permission_denied(path: &CStr) -> bool {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(OsStr::from_bytes(path.to_bytes()))
+        .is_err_and(|err| err.kind() == io::ErrorKind::PermissionDenied)
+}
+
+/// Opens a device node, checking that it still has the serial number it was found with
+pub fn open_path(
+    hid_api: &HidApi,
+    path: &CStr,
+    expected_serial: Option<&str>,
+) -> Result<HidDevice, OpenFirstMouseError> {
+    let device = hid_api.open_path(path).map_err(|err| {
+        if permission_denied(path) {
+            OpenFirstMouseError::PermissionDenied(PathBuf::from(OsStr::from_bytes(path.to_bytes())))
+        } else {
+            err.into()
+        }
+    })?;
+    // The device list may be stale, so make sure the node still belongs to the same device
+    if let (Some(expected), Ok(serial)) = (expected_serial, device.get_serial_number_string()) {
+        if serial.as_deref() != Some(expected) {
+            return Err(OpenFirstMouseError::Replaced);
+        }
+    }
+    Ok(device)
+}
+
+/// HID API shared with the blocking threads devices are opened on
+pub type SharedHidApi = Arc<Mutex<HidApi>>;
+
+/// Locks the HID API, carrying on if a blocking thread panicked while holding it
+pub fn lock_hid_api(hid_api: &SharedHidApi) -> MutexGuard<'_, HidApi> {
+    hid_api.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Runs blocking HID I/O on the blocking thread pool so the runtime keeps serving udev and signals
+///
+/// Logs from `f` stay in the caller's span
+pub(crate) async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    let span = Span::current();
+    match tokio::task::spawn_blocking(move || span.in_scope(f)).await {
+        Ok(value) => value,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// Whether the hint about udev rules has been logged
+static PERMISSION_HINT_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Explains once how to grant access to a mouse we found but may not open
+fn permission_hint(profile: &MouseProfile) {
+    if PERMISSION_HINT_SHOWN.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    error!(
+        "The mouse was found but its device node isn't accessible to this user. A udev rule \
+         granting access is likely needed, e.g. in /etc/udev/rules.d/70-nezumi.rules: \
+         KERNEL==\"hidraw*\", ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\", \
+         TAG+=\"uaccess\"",
+        profile.vendor, profile.product
+    );
+}
+
+/// Opens the device of a found profile
+#[instrument(name = "mouse", skip_all, fields(name = candidate.name, model = %candidate.profile.model))]
+pub async fn open_candidate(
+    hid_api: &SharedHidApi,
+    candidate: &Candidate<'_>,
+    read_timeout: i32,
+) -> Result<Box<dyn Mouse>, OpenFirstMouseError> {
+    info!("Found {}", candidate.name);
+    let hid_api = Arc::clone(hid_api);
+    let path = candidate.path.clone();
+    let expected_serial = candidate.serial.clone();
+    let device =
+        blocking(move || open_path(&lock_hid_api(&hid_api), &path, expected_serial.as_deref()))
+            .await
+            .inspect_err(|err| {
+                if let OpenFirstMouseError::PermissionDenied(_) = err {
+                    permission_hint(candidate.profile);
+                }
+            })?;
+    Ok(mouse::get_mouse(
+        &candidate.profile.model,
+        device,
+        &candidate.profile.driver_options(read_timeout),
+    )?)
+}
+
+/// Opens the mouse described by a profile, if it is connected, along with the node it is on
+pub async fn open_profile(
+    hid_api: &SharedHidApi,
+    name: &str,
+    profile: &MouseProfile,
+    read_timeout: i32,
+) -> Result<Option<(Box<dyn Mouse>, Node)>, OpenFirstMouseError> {
+    let candidate = scan(&lock_hid_api(hid_api), std::iter::once((name, profile))).pop();
+    match candidate {
+        Some(candidate) => Ok(Some((
+            open_candidate(hid_api, &candidate, read_timeout).await?,
+            candidate.node(),
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Opens a mouse on the device node it was last opened on, without refreshing the device list
+///
+/// Returns `None` unless the node is there again and belongs to a device with the same serial
+/// number. Without a serial number there is no telling whether the node went to another device
+pub async fn open_node(
+    hid_api: &SharedHidApi,
+    name: &str,
+    profile: &MouseProfile,
+    node: &Node,
+    read_timeout: i32,
+) -> Result<Option<Box<dyn Mouse>>, OpenFirstMouseError> {
+    if node.serial.is_none() || !node_exists(&node.path) {
+        return Ok(None);
+    }
+    let candidate = Candidate {
+        name,
+        profile,
+        path: node.path.clone(),
+        serial: node.serial.clone(),
+    };
+    match open_candidate(hid_api, &candidate, read_timeout).await {
+        Ok(mouse) => Ok(Some(mouse)),
+        Err(OpenFirstMouseError::Replaced) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Opens the first connected mouse of the profiles
+pub async fn open_first_mouse<'a>(
+    hid_api: &SharedHidApi,
+    mice: impl Iterator<Item = (&'a String, &'a MouseProfile)>,
+    read_timeout: i32,
+) -> Result<OpenMouse<'a>, OpenFirstMouseError> {
+    let candidate = scan(&lock_hid_api(hid_api), by_name(mice))
+        .into_iter()
+        .next();
+    let candidate = candidate.ok_or(OpenFirstMouseError::NotFound)?;
+    let mouse = open_candidate(hid_api, &candidate, read_timeout).await?;
+    let mut open_mouse = OpenMouse::new(candidate.name, candidate.profile, mouse);
+    open_mouse.node = Some(candidate.node());
+    Ok(open_mouse)
+}
+
+/// Opens the first connected mouse, trying again `retries` times if that fails
+pub async fn open_first_mouse_retrying<'a>(
+    hid_api: &SharedHidApi,
+    mouse_config: &'a Config,
+    read_timeout: i32,
+    retries: u32,
+) -> Result<OpenMouse<'a>, OpenFirstMouseError> {
+    let mut attempt = 0;
+    loop {
+        match open_first_mouse(hid_api, mouse_config.profiles.iter(), read_timeout).await {
+            Err(err) if attempt < retries => {
+                warn!("Error opening first mouse, retrying in {REOPEN_DELAY:?}: {err}");
+                time::sleep(REOPEN_DELAY).await;
+                // The mouse may have only just connected
+                if let Err(err) = lock_hid_api(hid_api).refresh_devices() {
+                    error!("Error refreshing HID device list: {err}");
+                }
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Opens every connected mouse, in config order
+///
+/// A mouse that fails to open is logged and skipped
+pub async fn open_all_mice<'a>(
+    hid_api: &SharedHidApi,
+    mice: impl Iterator<Item = (&'a String, &'a MouseProfile)>,
+    read_timeout: i32,
+) -> Vec<OpenMouse<'a>> {
+    let candidates = scan(&lock_hid_api(hid_api), by_name(mice));
+    let mut open_mice = Vec::new();
+    for candidate in candidates {
+        match open_candidate(hid_api, &candidate, read_timeout).await {
+            Ok(mouse) => {
+                let mut open_mouse = OpenMouse::new(candidate.name, candidate.profile, mouse);
+                open_mouse.node = Some(candidate.node());
+                open_mice.push(open_mouse);
+            }
+            Err(err) => error!("Error opening {}: {err}", candidate.name),
+        }
+    }
+    open_mice
+}
+
+/// How many times to try reopening a mouse that failed to read before waiting on udev
+const REOPEN_ATTEMPTS: u32 = 2;
+/// Delay before each attempt at reopening a mouse
+const REOPEN_DELAY: Duration = Duration::from_millis(250);
+
+/// Tries to reopen the device of a mouse that can no longer be read from
+///
+/// Returns `None` if the mouse is genuinely gone
+pub async fn reopen(
+    hid_api: &SharedHidApi,
+    open_mouse: &OpenMouse<'_>,
+    read_timeout: i32,
+) -> Option<(Box<dyn Mouse>, Node)> {
+    for attempt in 0..REOPEN_ATTEMPTS {
+        time::sleep(REOPEN_DELAY).await;
+        // Try the device we already know about before enumerating every device again
+        if attempt > 0 {
+            if let Err(err) = lock_hid_api(hid_api).refresh_devices() {
+                error!("Error refreshing HID device list: {err}");
+                continue;
+            }
+        }
+        match open_profile(hid_api, open_mouse.name, open_mouse.profile, read_timeout).await {
+            Ok(Some(opened)) => {
+                info!("Reopened {}", open_mouse.name);
+                return Some(opened);
+            }
+            Ok(None) => {}
+            Err(err) => error!("Error reopening {}: {err}", open_mouse.name),
+        }
+    }
+    None
+}
+
+/// Finds the connected interface of each profile, in config order
+///
+/// Profiles are looked up by vendor and product id in a single pass over the device list.
+/// Interfaces whose device node has disappeared since the list was refreshed are skipped. If
+/// several devices match a profile the first is used, with a warning
+pub fn scan<'a>(
+    hid_api: &HidApi,
+    mice: impl Iterator<Item = (&'a str, &'a MouseProfile)>,
+) -> Vec<Candidate<'a>> {
+    // Every matching device node of each profile along with its rank and serial number
+    let mut found = Vec::new();
+    let mut by_id: HashMap<(u16, u16), Vec<usize>> = HashMap::new();
+    for (index, (name, profile)) in mice.enumerate() {
+        found.push((name, profile, Vec::new()));
+        // A pinned device node is opened directly instead of scanning every device
+        if let Interface::Path(ref path) = profile.interface {
+            if node_exists(path) {
+                found[index].2.push((0, path.clone(), None));
+            }
+            continue;
+        }
+        by_id
+            .entry((profile.vendor, profile.product))
+            .or_default()
+            .push(index);
+    }
+    for device in hid_api.device_list() {
+        let Some(indices) = by_id.get(&(device.vendor_id(), device.product_id())) else {
+            continue;
+        };
+        for &index in indices {
+            let (_, profile, interfaces) = &mut found[index];
+            // A node with several top-level collections is listed once for each of them
+            let Some(rank) = profile.rank(device) else {
+                continue;
+            };
+            if !interfaces
+                .iter()
+                .any(|(_, path, _)| path.as_c_str() == device.path())
+                && node_exists(device.path())
+            {
+                interfaces.push((
+                    rank,
+                    device.path().to_owned(),
+                    device.serial_number().map(str::to_owned),
+                ));
+            }
+        }
+    }
+    found
+        .into_iter()
+        .filter_map(|(name, profile, mut interfaces)| {
+            // The most preferred interface wins, several of them mean several devices
+            interfaces.sort_by_key(|(rank, _, _)| *rank);
+            let best = interfaces.first()?.0;
+            interfaces.retain(|(rank, _, _)| *rank == best);
+            if interfaces.len() > 1 {
+                let serials: Vec<&str> = interfaces
+                    .iter()
+                    .map(|(_, _, serial)| {
+                        serial
+                            .as_deref()
+                            .filter(|serial| !serial.is_empty())
+                            .unwrap_or("(unknown)")
+                    })
+                    .collect();
+                warn!(
+                    "{} devices match profile {name}, using the first. Set `serial` in the \
+                     profile to pick one, the serial numbers are: {}",
+                    interfaces.len(),
+                    serials.join(", ")
+                );
+            }
+            let (_, path, serial) = interfaces.into_iter().next()?;
+            Some(Candidate {
+                name,
+                profile,
+                path,
+                serial,
+            })
+        })
+        .collect()
+}
+
+/// Borrows profile names as `str`
+pub fn by_name<'a>(
+    mice: impl Iterator<Item = (&'a String, &'a MouseProfile)>,
+) -> impl Iterator<Item = (&'a str, &'a MouseProfile)> {
+    mice.map(|(name, profile)| (name.as_str(), profile))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OpenFirstMouseError {
+    #[error("No mouse found, none of the profiles match a connected device")]
+    NotFound,
+    #[error("A different device now has the mouse's device node")]
+    Replaced,
+    #[error("Error opening the found mouse: {0}")]
+    OpenMouse(#[from] hidapi::HidError),
+    #[error("No permission to open the found mouse at {}", .0.display())]
+    PermissionDenied(PathBuf),
+    #[error("Found a mouse, but its model is not supported: {0}")]
+    WrapMouse(#[from] mouse::GetMouseError),
+}
@@ -0,0 +1,89 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+//! Durations given on the command line
+use std::time::Duration;
+
+/// Parses a duration such as `30`, `45s`, `5m`, `1h` or `1h30m`
+///
+/// A bare number is a number of seconds
+pub fn parse(s: &str) -> Result<Duration, DurationError> {
+    if s.is_empty() {
+        return Err(DurationError::Empty);
+    }
+    if let Ok(secs) = s.parse() {
+        return Ok(Duration::from_secs(secs));
+    }
+    let mut total: u64 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(DurationError::MissingNumber(rest.to_owned()));
+        }
+        let value: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| DurationError::Overflow)?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            unit => return Err(DurationError::UnknownUnit(unit.to_owned())),
+        };
+        rest = &rest[unit_len..];
+        total = value
+            .checked_mul(scale)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or(DurationError::Overflow)?;
+    }
+    Ok(Duration::from_secs(total))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DurationError {
+    #[error("duration is empty")]
+    Empty,
+    #[error("expected a number before `{0}`")]
+    MissingNumber(String),
+    #[error("unknown unit `{0}`, expected s, m or h")]
+    UnknownUnit(String),
+    #[error("duration is too long")]
+    Overflow,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        assert_eq!(parse("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse("0").unwrap(), Duration::ZERO);
+        assert_eq!(parse("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse("1h30m").unwrap(), Duration::from_secs(5400));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse("").is_err());
+        assert!(parse("m").is_err());
+        assert!(parse("5x").is_err());
+        assert!(parse("5 m").is_err());
+        assert!(parse("-5").is_err());
+        assert!(parse("99999999999999999999h").is_err());
+    }
+}
@@ -0,0 +1,722 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use crate::mouse::{BatteryStatus, Connection, MouseState};
+use crate::template::{Placeholder, Template};
+use crate::thresholds::{Level, Thresholds};
+use linked_hash_map::LinkedHashMap;
+use std::cell::RefCell;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Nerd Font bolt glyph shown while charging
+const ICON_CHARGING: &str = "\u{f0e7}";
+/// Nerd Font plug glyph shown once charged
+const ICON_CHARGED: &str = "\u{f1e6}";
+/// ANSI escapes coloring human readable output
+const COLOR_CRITICAL: &str = "\x1b[1;31m";
+const COLOR_LOW: &str = "\x1b[31m";
+const COLOR_CHARGING: &str = "\x1b[32m";
+const COLOR_RESET: &str = "\x1b[0m";
+/// i3bar block colors, i3status' defaults for bad, degraded and good
+const I3BAR_CRITICAL: &str = "#FF0000";
+const I3BAR_LOW: &str = "#FFFF00";
+const I3BAR_CHARGING: &str = "#00FF00";
+/// Appended to the text of a stale reading
+const STALE_SUFFIX: &str = " (stale)";
+
+/// Battery glyph used from a percentage upwards
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct RampStep {
+    /// Lowest percentage the glyph is used for
+    pub min: u16,
+    pub icon: String,
+}
+
+/// Glyphs used for the `{icon}` and `{charging}` placeholders
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Icons {
+    /// Battery glyphs, the one with the highest `min` at or below the percentage is used
+    pub ramp: Vec<RampStep>,
+    /// Show a bolt while the mouse is charging
+    pub charging_bolt: bool,
+    /// Shown instead of the bolt once the battery is full but still on the charger
+    pub charged: String,
+    /// Shown in turn instead of the bolt while charging, advancing with each reading. Waybar
+    /// output prefixes its text with them too
+    pub charging_frames: Vec<String>,
+}
+
+impl Default for Icons {
+    /// Nerd Font battery glyphs from empty to full, each used for the nearest quarter
+    fn default() -> Self {
+        let step = |min, icon: &str| RampStep {
+            min,
+            icon: icon.to_owned(),
+        };
+        Icons {
+            ramp: vec![
+                step(0, "\u{f244}"),
+                step(13, "\u{f243}"),
+                step(38, "\u{f242}"),
+                step(63, "\u{f241}"),
+                step(88, "\u{f240}"),
+            ],
+            charging_bolt: true,
+            charged: ICON_CHARGED.to_owned(),
+            charging_frames: Vec::new(),
+        }
+    }
+}
+
+impl Icons {
+    /// Picks the battery glyph for a percentage
+    pub fn battery(&self, percent: u16) -> &str {
+        self.ramp
+            .iter()
+            .filter(|step| step.min <= percent)
+            .max_by_key(|step| step.min)
+            .map_or("", |step| step.icon.as_str())
+    }
+
+    /// Glyph shown while charging, empty if disabled or discharging
+    ///
+    /// A full battery that is still on the charger gets the static charged glyph, otherwise
+    /// `frame` picks one of the charging frames if there are any
+    pub fn charging(&self, is_charging: bool, full: bool, frame: usize) -> &str {
+        if !is_charging {
+            ""
+        } else if full {
+            &self.charged
+        } else if !self.charging_frames.is_empty() {
+            &self.charging_frames[frame % self.charging_frames.len()]
+        } else if self.charging_bolt {
+            ICON_CHARGING
+        } else {
+            ""
+        }
+    }
+}
+
+/// Output format for battery readings
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Format {
+    /// Line rendered from the format template, by default a battery glyph and the percentage
+    Human,
+    /// Single-line JSON object
+    Json,
+    /// Stream of JSON objects like `json`, each with a `ts` field counting seconds since
+    /// startup, without a line clearing the status when the mouse disconnects
+    Jsonl,
+    /// JSON object for a Waybar custom module
+    Waybar,
+    /// Just the battery percentage as a number, for scripts
+    Percent,
+    /// i3bar protocol, a header and then an array with a block for each mouse on every reading
+    I3bar,
+}
+
+/// A single battery reading to be formatted
+pub struct Reading<'a> {
+    /// Name of the profile the mouse was opened with
+    pub name: &'a str,
+    /// Model name of the mouse
+    pub model: &'a str,
+    /// What the mouse reported
+    pub state: MouseState,
+    /// Estimated time until the battery is empty, `None` if unknown or charging
+    pub time_remaining: Option<Duration>,
+    /// DPI setting, `None` if not requested or unsupported
+    pub dpi: Option<u16>,
+    /// Whether the reading came from the mouse itself, `None` if the model doesn't say
+    pub connection: Option<Connection>,
+    /// Whether this is the last reading of a mouse that stopped answering, older than
+    /// `--stale-after`, or one saved by the last run
+    pub stale: bool,
+    /// Which charging frame to show, counting up while the mouse charges
+    pub frame: usize,
+}
+
+/// Formats battery readings in the user's chosen format
+pub struct Formatter {
+    format: Format,
+    thresholds: Thresholds,
+    /// Template human readable output is rendered from
+    template: Template,
+    /// Glyphs substituted into the template
+    icons: Icons,
+    /// Prefix human readable and percent output with the profile name
+    show_name: bool,
+    /// Printed by the percent format when the battery is unknown, nothing at all if `None`
+    percent_unknown: Option<String>,
+    /// Color human readable output by battery level
+    color: bool,
+    /// Latest i3bar block of each mouse by profile name, since every status line holds them all
+    i3bar_blocks: RefCell<LinkedHashMap<String, I3barBlock>>,
+}
+
+/// When the first formatter was made, `ts` in the JSON lines format counts from here so it keeps
+/// increasing when the formatter is rebuilt on reload
+static START: OnceLock<Instant> = OnceLock::new();
+
+impl Formatter {
+    pub fn new(
+        format: Format,
+        thresholds: Thresholds,
+        template: Template,
+        icons: Icons,
+        show_name: bool,
+        percent_unknown: Option<String>,
+        color: bool,
+    ) -> Self {
+        START.get_or_init(Instant::now);
+        Formatter {
+            format,
+            thresholds,
+            template,
+            icons,
+            show_name,
+            percent_unknown,
+            color,
+            i3bar_blocks: RefCell::new(LinkedHashMap::new()),
+        }
+    }
+
+    /// Formats a single battery reading as one line of output
+    ///
+    /// Returns `None` if nothing should be printed for this reading
+    pub fn format(&self, reading: &Reading) -> serde_json::Result<Option<String>> {
+        match self.format {
+            Format::Human => Ok(self.human(reading, self.color)),
+            Format::Json => json(reading).map(Some),
+            Format::Jsonl => serde_json::to_string(&JsonStatus {
+                ts: Some(START.get_or_init(Instant::now).elapsed().as_secs_f64()),
+                ..json_status(reading)
+            })
+            .map(Some),
+            Format::Waybar => serde_json::to_string(&self.waybar(reading)).map(Some),
+            Format::Percent => {
+                let line = match reading.state {
+                    MouseState::Battery(status) => status.percent.to_string(),
+                    MouseState::Asleep | MouseState::NoResponse => match &self.percent_unknown {
+                        Some(unknown) => unknown.clone(),
+                        None => return Ok(None),
+                    },
+                };
+                let line = if reading.stale {
+                    format!("{line}{STALE_SUFFIX}")
+                } else {
+                    line
+                };
+                Ok(Some(if self.show_name {
+                    format!("{}: {line}", reading.name)
+                } else {
+                    line
+                }))
+            }
+            Format::I3bar => {
+                let Some(full_text) = self.human(reading, false) else {
+                    return Ok(None);
+                };
+                let (short_text, color) = match reading.state {
+                    MouseState::Battery(status) => (
+                        if reading.stale {
+                            format!("{}%{STALE_SUFFIX}", status.percent)
+                        } else {
+                            format!("{}%", status.percent)
+                        },
+                        match self.thresholds.classify(status.percent, status.is_charging) {
+                            Level::Critical => Some(I3BAR_CRITICAL),
+                            Level::Low => Some(I3BAR_LOW),
+                            Level::Charging => Some(I3BAR_CHARGING),
+                            Level::Normal | Level::Full => None,
+                        },
+                    ),
+                    MouseState::Asleep | MouseState::NoResponse => ("off".to_owned(), None),
+                };
+                let block = I3barBlock {
+                    name: "nezumi",
+                    instance: reading.name.to_owned(),
+                    full_text,
+                    short_text,
+                    color,
+                };
+                let mut blocks = self.i3bar_blocks.borrow_mut();
+                match blocks.get_mut(reading.name) {
+                    Some(shown) => *shown = block,
+                    None => {
+                        blocks.insert(reading.name.to_owned(), block);
+                    }
+                }
+                i3bar_line(&blocks).map(Some)
+            }
+        }
+    }
+
+    /// Lines printed once before any reading, `None` for formats without a header
+    ///
+    /// The i3bar header is followed by the opening of the endless array of status lines and an
+    /// empty first line
+    pub fn header(&self) -> Option<String> {
+        match self.format {
+            Format::I3bar => Some(format!("{}\n[\n[]", serde_json::json!({ "version": 1 }))),
+            Format::Human | Format::Json | Format::Jsonl | Format::Waybar | Format::Percent => None,
+        }
+    }
+
+    /// Renders a reading from the template, `None` if nothing should be printed
+    fn human(&self, reading: &Reading, color: bool) -> Option<String> {
+        let line = match reading.state {
+            MouseState::Battery(status) => {
+                let line = self.template.render(|placeholder| match placeholder {
+                    Placeholder::Percent => status.percent.to_string(),
+                    Placeholder::Charging => self
+                        .icons
+                        .charging(status.is_charging, self.is_full(status), reading.frame)
+                        .to_owned(),
+                    Placeholder::Model => reading.model.to_owned(),
+                    Placeholder::Name => reading.name.to_owned(),
+                    Placeholder::Icon => self.icons.battery(status.percent).to_owned(),
+                });
+                if color {
+                    self.colored(line, status)
+                } else {
+                    line
+                }
+            }
+            MouseState::Asleep => "mouse off".to_owned(),
+            MouseState::NoResponse => return None,
+        };
+        let line = match reading.dpi {
+            Some(dpi) => format!("{line} {dpi} DPI"),
+            None => line,
+        };
+        let line = if reading.stale {
+            format!("{line}{STALE_SUFFIX}")
+        } else {
+            line
+        };
+        Some(if self.show_name {
+            format!("{}: {line}", reading.name)
+        } else {
+            line
+        })
+    }
+
+    /// Whether a reading looks different with the next charging frame
+    pub fn animates(&self, state: MouseState) -> bool {
+        let MouseState::Battery(status) = state else {
+            return false;
+        };
+        matches!(self.format, Format::Human | Format::Waybar | Format::I3bar)
+            && status.is_charging
+            && !self.is_full(status)
+            && !self.icons.charging_frames.is_empty()
+    }
+
+    /// Whether the battery is at or above the full threshold
+    fn is_full(&self, status: BatteryStatus) -> bool {
+        self.thresholds.classify(status.percent, status.is_charging) == Level::Full
+    }
+
+    /// Colors a human readable line by battery level
+    fn colored(&self, line: String, status: BatteryStatus) -> String {
+        let color = match self.thresholds.classify(status.percent, status.is_charging) {
+            Level::Critical => COLOR_CRITICAL,
+            Level::Low => COLOR_LOW,
+            Level::Charging => COLOR_CHARGING,
+            Level::Normal | Level::Full => return line,
+        };
+        format!("{color}{line}{COLOR_RESET}")
+    }
+
+    /// Line showing the remaining mice once one of them is gone, `None` if nothing changes
+    ///
+    /// Only i3bar output shows several mice on one line, the other formats keep their last line
+    /// until [`Formatter::disconnected`]
+    pub fn remove(&self, name: &str) -> Option<serde_json::Result<String>> {
+        let mut blocks = self.i3bar_blocks.borrow_mut();
+        blocks.remove(name)?;
+        Some(i3bar_line(&blocks))
+    }
+
+    /// Line clearing the last reading once no mouse is being tracked
+    ///
+    /// Bar formats get an empty line, JSON an object with a `disconnected` state and no battery,
+    /// and the percent format its placeholder for an unknown battery. The JSON lines stream
+    /// gets nothing, nor does i3bar once every block was removed
+    pub fn disconnected(&self) -> Option<String> {
+        match self.format {
+            Format::Human | Format::Waybar => Some(String::new()),
+            Format::I3bar => {
+                let mut blocks = self.i3bar_blocks.borrow_mut();
+                if blocks.is_empty() {
+                    return None;
+                }
+                blocks.clear();
+                Some(",[]".to_owned())
+            }
+            Format::Percent => Some(self.percent_unknown.clone().unwrap_or_default()),
+            Format::Json => Some(
+                serde_json::json!({
+                    "state": "disconnected",
+                    "percent": null,
+                    "charging": null,
+                })
+                .to_string(),
+            ),
+            Format::Jsonl => None,
+        }
+    }
+
+    fn waybar(&self, reading: &Reading) -> WaybarStatus {
+        let mut status = self.waybar_status(reading);
+        if self.animates(reading.state) {
+            let frame = self.icons.charging(true, false, reading.frame);
+            status.text = format!("{frame} {}", status.text);
+        }
+        if let Some(dpi) = reading.dpi {
+            status.tooltip = format!("{}, {dpi} DPI", status.tooltip);
+        }
+        if reading.connection == Some(Connection::Stale) {
+            status.tooltip = format!("{}, last known level", status.tooltip);
+        }
+        if reading.stale {
+            status.tooltip = format!("{}{STALE_SUFFIX}", status.tooltip);
+        }
+        status
+    }
+
+    fn waybar_status(&self, reading: &Reading) -> WaybarStatus {
+        match reading.state {
+            MouseState::Battery(status) => WaybarStatus {
+                text: format!("{}%", status.percent),
+                tooltip: match reading.time_remaining {
+                    Some(remaining) => format!(
+                        "{} — discharging, about {} min remaining",
+                        reading.name,
+                        minutes(remaining)
+                    ),
+                    None => format!(
+                        "{} — {}",
+                        reading.name,
+                        if status.is_charging && self.is_full(status) {
+                            "charged"
+                        } else if status.is_charging {
+                            "charging"
+                        } else {
+                            "discharging"
+                        }
+                    ),
+                },
+                class: match self.thresholds.classify(status.percent, status.is_charging) {
+                    Level::Critical => Some("critical"),
+                    Level::Low => Some("warning"),
+                    Level::Normal => None,
+                    Level::Charging => Some("charging"),
+                    Level::Full => Some("full"),
+                },
+                percentage: Some(status.percent),
+                time_remaining_min: reading.time_remaining.map(minutes),
+            },
+            MouseState::Asleep => WaybarStatus {
+                text: "off".to_owned(),
+                tooltip: format!("{} — mouse off", reading.name),
+                class: Some("asleep"),
+                percentage: None,
+                time_remaining_min: None,
+            },
+            MouseState::NoResponse => WaybarStatus {
+                text: String::new(),
+                tooltip: format!("{} — no response", reading.name),
+                class: Some("unknown"),
+                percentage: None,
+                time_remaining_min: None,
+            },
+        }
+    }
+}
+
+/// Formats a battery reading as a single-line JSON object, as in [`Format::Json`]
+pub fn json(reading: &Reading) -> serde_json::Result<String> {
+    serde_json::to_string(&json_status(reading))
+}
+
+fn json_status<'a>(reading: &Reading<'a>) -> JsonStatus<'a> {
+    let status = match reading.state {
+        MouseState::Battery(status) => Some(status),
+        MouseState::Asleep | MouseState::NoResponse => None,
+    };
+    JsonStatus {
+        state: match reading.state {
+            MouseState::Battery(_) => "battery",
+            MouseState::Asleep => "asleep",
+            MouseState::NoResponse => "no_response",
+        },
+        percent: status.map(|status| status.percent),
+        charging: status.map(|status| status.is_charging),
+        name: reading.name,
+        model: reading.model,
+        time_remaining_min: reading.time_remaining.map(minutes),
+        dpi: reading.dpi,
+        connection: reading.connection.map(|connection| match connection {
+            Connection::Connected => "connected",
+            Connection::Stale => "stale",
+        }),
+        ts: None,
+        stale: reading.stale,
+    }
+}
+
+/// Status line of the i3bar protocol holding every block
+///
+/// The header opened the array with an empty line, so every line continues it
+fn i3bar_line(blocks: &LinkedHashMap<String, I3barBlock>) -> serde_json::Result<String> {
+    let blocks: Vec<_> = blocks.values().collect();
+    serde_json::to_string(&blocks).map(|blocks| format!(",{blocks}"))
+}
+
+/// Rounds a duration to whole minutes
+pub fn minutes(duration: Duration) -> u64 {
+    (duration.as_secs() + 30) / 60
+}
+
+/// Battery reading as emitted by [`Format::Json`]
+#[derive(serde::Serialize)]
+struct JsonStatus<'a> {
+    /// `battery` if the mouse reported its battery, `asleep` if it is off or `no_response`, see
+    /// also [`Formatter::disconnected`]
+    state: &'static str,
+    /// Battery percentage, `null` unless the mouse reported its battery
+    percent: Option<u16>,
+    /// Whether the mouse is charging, `null` if unknown
+    charging: Option<bool>,
+    /// Name of the profile the mouse was opened with
+    name: &'a str,
+    /// Model name of the mouse
+    model: &'a str,
+    /// Estimated minutes until the battery is empty, `null` if unknown or charging
+    time_remaining_min: Option<u64>,
+    /// DPI setting, omitted if not requested or unsupported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dpi: Option<u16>,
+    /// `connected` if the mouse answered, `stale` if the receiver answered for it, omitted if
+    /// unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection: Option<&'static str>,
+    /// Seconds since startup on a monotonic clock, only in [`Format::Jsonl`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<f64>,
+    /// `true` if this is the last reading of a mouse that stopped answering, older than
+    /// `--stale-after`, omitted otherwise
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+}
+
+/// Block of the i3bar protocol, see `i3bar-protocol(7)`
+#[derive(serde::Serialize)]
+struct I3barBlock {
+    name: &'static str,
+    /// Profile name, telling the blocks of several mice apart in click events
+    instance: String,
+    full_text: String,
+    /// Shown instead of `full_text` when the bar runs out of space
+    short_text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<&'static str>,
+}
+
+/// Battery reading as emitted by [`Format::Waybar`]
+///
+/// See `waybar-custom(5)` for the meaning of each field
+#[derive(serde::Serialize)]
+struct WaybarStatus {
+    text: String,
+    tooltip: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentage: Option<u16>,
+    /// Not read by Waybar, but available to scripts consuming the output
+    time_remaining_min: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icon_ramp() {
+        let icons = Icons::default();
+        assert_eq!(icons.battery(0), "\u{f244}");
+        assert_eq!(icons.battery(12), "\u{f244}");
+        assert_eq!(icons.battery(50), "\u{f242}");
+        assert_eq!(icons.battery(100), "\u{f240}");
+        let icons = Icons {
+            ramp: vec![RampStep {
+                min: 20,
+                icon: "ok".into(),
+            }],
+            charging_bolt: false,
+            charged: "full".into(),
+            charging_frames: Vec::new(),
+        };
+        assert_eq!(icons.battery(10), "");
+        assert_eq!(icons.battery(30), "ok");
+        assert_eq!(icons.charging(true, false, 0), "");
+        assert_eq!(icons.charging(true, true, 0), "full");
+        assert_eq!(icons.charging(false, true, 0), "");
+        let icons = Icons::default();
+        assert_eq!(icons.charging(true, false, 0), ICON_CHARGING);
+        assert_eq!(icons.charging(true, true, 0), ICON_CHARGED);
+        let icons = Icons {
+            charging_frames: vec!["a".into(), "b".into()],
+            ..Icons::default()
+        };
+        assert_eq!(icons.charging(true, false, 0), "a");
+        assert_eq!(icons.charging(true, false, 3), "b");
+        assert_eq!(icons.charging(true, true, 3), ICON_CHARGED);
+        assert_eq!(icons.charging(false, false, 3), "");
+    }
+
+    #[test]
+    fn percent() {
+        let formatter = |percent_unknown: Option<&str>| {
+            Formatter::new(
+                Format::Percent,
+                Thresholds::default(),
+                Template::default(),
+                Icons::default(),
+                false,
+                percent_unknown.map(str::to_owned),
+                false,
+            )
+        };
+        let reading = |state| Reading {
+            name: "wireless",
+            model: "steelseries_aerox_9_wireless",
+            state,
+            time_remaining: None,
+            dpi: Some(800),
+            connection: None,
+            stale: false,
+            frame: 0,
+        };
+        let battery = MouseState::Battery(BatteryStatus {
+            is_charging: true,
+            percent: 45,
+        });
+        let quiet = formatter(None);
+        assert_eq!(quiet.format(&reading(battery)).unwrap().unwrap(), "45");
+        assert_eq!(quiet.format(&reading(MouseState::Asleep)).unwrap(), None);
+        assert_eq!(quiet.disconnected().unwrap(), "");
+        let placeholder = formatter(Some("-1"));
+        assert_eq!(
+            placeholder
+                .format(&reading(MouseState::NoResponse))
+                .unwrap()
+                .unwrap(),
+            "-1"
+        );
+        assert_eq!(placeholder.disconnected().unwrap(), "-1");
+    }
+
+    #[test]
+    fn i3bar() {
+        let formatter = Formatter::new(
+            Format::I3bar,
+            Thresholds::default(),
+            "{percent}%".parse().unwrap(),
+            Icons::default(),
+            false,
+            None,
+            true,
+        );
+        assert_eq!(formatter.header().unwrap(), "{\"version\":1}\n[\n[]");
+        let line = formatter
+            .format(&Reading {
+                name: "wireless",
+                model: "steelseries_aerox_9_wireless",
+                state: MouseState::Battery(BatteryStatus {
+                    is_charging: false,
+                    percent: 5,
+                }),
+                time_remaining: None,
+                dpi: None,
+                connection: None,
+                stale: false,
+                frame: 0,
+            })
+            .unwrap()
+            .unwrap();
+        // Uncolored text, the color goes in its own field
+        assert_eq!(
+            line,
+            ",[{\"name\":\"nezumi\",\"instance\":\"wireless\",\"full_text\":\"5%\",\
+             \"short_text\":\"5%\",\"color\":\"#FF0000\"}]"
+        );
+        assert_eq!(formatter.disconnected().unwrap(), ",[]");
+        // Nothing left to clear
+        assert_eq!(formatter.disconnected(), None);
+    }
+
+    #[test]
+    fn i3bar_several_mice() {
+        let formatter = Formatter::new(
+            Format::I3bar,
+            Thresholds::default(),
+            "{percent}%".parse().unwrap(),
+            Icons::default(),
+            false,
+            None,
+            false,
+        );
+        let line = |name, percent| {
+            formatter
+                .format(&Reading {
+                    name,
+                    model: "steelseries_aerox_9_wireless",
+                    state: MouseState::Battery(BatteryStatus {
+                        is_charging: false,
+                        percent,
+                    }),
+                    time_remaining: None,
+                    dpi: None,
+                    connection: None,
+                    stale: false,
+                    frame: 0,
+                })
+                .unwrap()
+                .unwrap()
+        };
+        let block = |name, percent| {
+            format!(
+                "{{\"name\":\"nezumi\",\"instance\":\"{name}\",\"full_text\":\"{percent}%\",\
+                 \"short_text\":\"{percent}%\"}}"
+            )
+        };
+        assert_eq!(line("wired", 80), format!(",[{}]", block("wired", 80)));
+        // Every line holds each mouse, in the order they were first read
+        assert_eq!(
+            line("wireless", 45),
+            format!(",[{},{}]", block("wired", 80), block("wireless", 45))
+        );
+        assert_eq!(
+            line("wired", 75),
+            format!(",[{},{}]", block("wired", 75), block("wireless", 45))
+        );
+        // A mouse that is gone leaves the others
+        assert_eq!(
+            formatter.remove("wired").unwrap().unwrap(),
+            format!(",[{}]", block("wireless", 45))
+        );
+        assert!(formatter.remove("wired").is_none());
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use crate::mouse::BatteryStatus;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of samples the discharge rate is fitted over
+pub const HISTORY_LEN: usize = 20;
+
+/// Rolling history of battery readings while discharging
+#[derive(Debug, Default)]
+pub struct History {
+    /// Time and percentage of each reading, oldest first
+    samples: VecDeque<(Instant, u16)>,
+}
+
+impl History {
+    /// Records a reading, forgetting the discharge history whenever the mouse charges
+    pub fn push(&mut self, at: Instant, status: &BatteryStatus) {
+        if status.is_charging {
+            self.samples.clear();
+            return;
+        }
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((at, status.percent));
+    }
+
+    /// Estimates how long until the battery is empty from a linear fit of the history
+    ///
+    /// Returns `None` if there are too few samples or the battery is not draining
+    pub fn time_remaining(&self) -> Option<Duration> {
+        let (first, _) = *self.samples.front()?;
+        let (_, current) = *self.samples.back()?;
+        let points = self
+            .samples
+            .iter()
+            .map(|(at, percent)| (at.duration_since(first).as_secs_f64(), f64::from(*percent)));
+        let n = self.samples.len() as f64;
+        let (sum_x, sum_y) = points
+            .clone()
+            .fold((0.0, 0.0), |(sum_x, sum_y), (x, y)| (sum_x + x, sum_y + y));
+        let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+        let (covariance, variance) = points.fold((0.0, 0.0), |(covariance, variance), (x, y)| {
+            (
+                covariance + (x - mean_x) * (y - mean_y),
+                variance + (x - mean_x).powi(2),
+            )
+        });
+        // All samples were taken at the same time
+        if variance == 0.0 {
+            return None;
+        }
+        // Percent per second
+        let slope = covariance / variance;
+        if slope >= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(f64::from(current) / -slope))
+    }
+}
+
+/// Moving average of the most recent battery percentages, to hide jitter in the readings
+#[derive(Debug)]
+pub struct Smoothing {
+    /// Number of readings averaged over
+    window: usize,
+    /// Most recent percentages, oldest first
+    samples: VecDeque<u16>,
+}
+
+impl Smoothing {
+    /// Averages over the last `window` readings, a window of 0 or 1 disables smoothing
+    pub fn new(window: usize) -> Self {
+        Smoothing {
+            window: window.max(1),
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Records a reading and replaces its percentage with the rounded average
+    ///
+    /// The charging state is passed through as is
+    pub fn smooth(&mut self, status: BatteryStatus) -> BatteryStatus {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(status.percent);
+        let len = self.samples.len() as u32;
+        let sum: u32 = self.samples.iter().copied().map(u32::from).sum();
+        BatteryStatus {
+            // The average of u16s always fits in a u16
+            percent: ((sum + len / 2) / len) as u16,
+            ..status
+        }
+    }
+}
+
+/// Holds back changes of the charging flag until enough consecutive readings agree, so a flag
+/// flickering at the charge boundary doesn't look like the mouse being plugged in and out
+#[derive(Debug)]
+pub struct ChargingDebounce {
+    /// Number of consecutive readings a change must be seen in
+    reads: usize,
+    /// Charging state passed on, `None` before the first reading
+    confirmed: Option<bool>,
+    /// Number of consecutive readings disagreeing with the confirmed state
+    disagreeing: usize,
+}
+
+impl ChargingDebounce {
+    /// Confirms a change after `reads` readings in a row, 0 or 1 confirms every change at once
+    pub fn new(reads: usize) -> Self {
+        ChargingDebounce {
+            reads: reads.max(1),
+            confirmed: None,
+            disagreeing: 0,
+        }
+    }
+
+    /// Records a reading and replaces its charging state with the confirmed one
+    ///
+    /// The first reading is taken as is
+    pub fn debounce(&mut self, status: BatteryStatus) -> BatteryStatus {
+        let confirmed = *self.confirmed.get_or_insert(status.is_charging);
+        if status.is_charging == confirmed {
+            self.disagreeing = 0;
+        } else {
+            self.disagreeing += 1;
+            if self.disagreeing >= self.reads {
+                self.confirmed = Some(status.is_charging);
+                self.disagreeing = 0;
+            }
+        }
+        BatteryStatus {
+            is_charging: self.confirmed.unwrap_or(status.is_charging),
+            ..status
+        }
+    }
+}
+
+/// Median of several readings
+///
+/// Between the two middle percentages the lower one is taken, and the mouse counts as charging
+/// if most of the readings say so. Returns `None` without readings
+pub fn median(readings: &[BatteryStatus]) -> Option<BatteryStatus> {
+    if readings.is_empty() {
+        return None;
+    }
+    let mut percents: Vec<u16> = readings.iter().map(|status| status.percent).collect();
+    percents.sort_unstable();
+    let charging = readings.iter().filter(|status| status.is_charging).count();
+    Some(BatteryStatus {
+        is_charging: charging * 2 > readings.len(),
+        percent: percents[(percents.len() - 1) / 2],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn discharging(percent: u16) -> BatteryStatus {
+        BatteryStatus {
+            is_charging: false,
+            percent,
+        }
+    }
+
+    #[test]
+    fn steady_drain() {
+        let start = Instant::now();
+        let mut history = History::default();
+        // One percent every minute
+        for (minute, percent) in (0..5).zip((46..=50).rev()) {
+            history.push(
+                start + Duration::from_secs(minute * 60),
+                &discharging(percent),
+            );
+        }
+        let remaining = history.time_remaining().unwrap();
+        assert_eq!(remaining.as_secs_f64().round(), 46.0 * 60.0);
+    }
+
+    #[test]
+    fn not_draining() {
+        let start = Instant::now();
+        let mut history = History::default();
+        assert_eq!(history.time_remaining(), None);
+        history.push(start, &discharging(50));
+        assert_eq!(history.time_remaining(), None);
+        history.push(start + Duration::from_secs(60), &discharging(50));
+        assert_eq!(history.time_remaining(), None);
+    }
+
+    #[test]
+    fn charging_resets() {
+        let start = Instant::now();
+        let mut history = History::default();
+        history.push(start, &discharging(50));
+        history.push(start + Duration::from_secs(60), &discharging(49));
+        history.push(
+            start + Duration::from_secs(120),
+            &BatteryStatus {
+                is_charging: true,
+                percent: 49,
+            },
+        );
+        assert_eq!(history.time_remaining(), None);
+    }
+
+    #[test]
+    fn bounded() {
+        let start = Instant::now();
+        let mut history = History::default();
+        for second in 0..HISTORY_LEN as u64 * 2 {
+            history.push(start + Duration::from_secs(second), &discharging(50));
+        }
+        assert_eq!(history.samples.len(), HISTORY_LEN);
+    }
+
+    #[test]
+    fn smoothing() {
+        let mut smoothing = Smoothing::new(3);
+        assert_eq!(smoothing.smooth(discharging(45)).percent, 45);
+        assert_eq!(smoothing.smooth(discharging(40)).percent, 43);
+        assert_eq!(smoothing.smooth(discharging(45)).percent, 43);
+        // The first reading has left the window
+        assert_eq!(smoothing.smooth(discharging(40)).percent, 42);
+        let mut disabled = Smoothing::new(0);
+        assert_eq!(disabled.smooth(discharging(45)).percent, 45);
+        assert_eq!(disabled.smooth(discharging(40)).percent, 40);
+    }
+
+    #[test]
+    fn median_reading() {
+        assert_eq!(median(&[]), None);
+        assert_eq!(median(&[discharging(45)]), Some(discharging(45)));
+        let readings = [discharging(45), discharging(5), discharging(40)];
+        assert_eq!(median(&readings), Some(discharging(40)));
+        let charging = BatteryStatus {
+            is_charging: true,
+            percent: 50,
+        };
+        let readings = [discharging(45), discharging(40), charging, discharging(50)];
+        assert_eq!(
+            median(&readings),
+            Some(BatteryStatus {
+                is_charging: false,
+                percent: 45
+            })
+        );
+    }
+
+    #[test]
+    fn charging_debounce() {
+        let charging = BatteryStatus {
+            is_charging: true,
+            percent: 99,
+        };
+        let mut debounce = ChargingDebounce::new(2);
+        assert!(!debounce.debounce(discharging(99)).is_charging);
+        // A single flicker is ignored
+        assert!(!debounce.debounce(charging).is_charging);
+        assert!(!debounce.debounce(discharging(99)).is_charging);
+        // Two in a row are a real change
+        assert!(!debounce.debounce(charging).is_charging);
+        assert!(debounce.debounce(charging).is_charging);
+        assert!(debounce.debounce(discharging(99)).is_charging);
+        let mut disabled = ChargingDebounce::new(1);
+        assert!(!disabled.debounce(discharging(99)).is_charging);
+        assert!(disabled.debounce(charging).is_charging);
+    }
+}
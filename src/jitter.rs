@@ -0,0 +1,136 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+//! Randomizing the polling interval
+use crate::duration::{self, DurationError};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How much to randomly lengthen or shorten each polling interval by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jitter {
+    /// A fraction of the interval, from 0 to 1
+    Fraction(f64),
+    /// A fixed duration
+    Absolute(Duration),
+}
+
+impl Default for Jitter {
+    fn default() -> Self {
+        Jitter::Absolute(Duration::ZERO)
+    }
+}
+
+impl Jitter {
+    /// Largest offset either way for an interval
+    ///
+    /// At most half the interval, so reads are never run back to back
+    pub fn bound(&self, interval: Duration) -> Duration {
+        let bound = match *self {
+            Jitter::Fraction(fraction) => interval.mul_f64(fraction),
+            Jitter::Absolute(jitter) => jitter,
+        };
+        bound.min(interval / 2)
+    }
+
+    /// Randomizes an interval by up to the jitter either way
+    pub fn apply(&self, interval: Duration) -> Duration {
+        let bound = self.bound(interval);
+        if bound.is_zero() {
+            return interval;
+        }
+        // Uniform over [-1, 1]
+        let offset = random_unit() * 2.0 - 1.0;
+        let shift = bound.mul_f64(offset.abs());
+        if offset < 0.0 {
+            interval.saturating_sub(shift)
+        } else {
+            interval + shift
+        }
+    }
+}
+
+/// A random number in [0, 1], good enough for spreading timers without a dependency
+///
+/// Every `RandomState` is seeded differently
+fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    // The top 53 bits fill an f64's mantissa
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl FromStr for Jitter {
+    type Err = JitterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('%') {
+            Some(percent) => {
+                let percent: f64 = percent
+                    .parse()
+                    .map_err(|_| JitterError::InvalidPercent(s.to_owned()))?;
+                if !(0.0..=100.0).contains(&percent) {
+                    return Err(JitterError::PercentOutOfRange(s.to_owned()));
+                }
+                Ok(Jitter::Fraction(percent / 100.0))
+            }
+            None => Ok(Jitter::Absolute(duration::parse(s)?)),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JitterError {
+    #[error("`{0}` is not a percentage")]
+    InvalidPercent(String),
+    #[error("`{0}` is not between 0% and 100%")]
+    PercentOutOfRange(String),
+    #[error(transparent)]
+    Duration(#[from] DurationError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("10%".parse::<Jitter>().unwrap(), Jitter::Fraction(0.1));
+        assert_eq!(
+            "5s".parse::<Jitter>().unwrap(),
+            Jitter::Absolute(Duration::from_secs(5))
+        );
+        assert!("150%".parse::<Jitter>().is_err());
+        assert!("x%".parse::<Jitter>().is_err());
+    }
+
+    #[test]
+    fn bounded() {
+        let interval = Duration::from_secs(30);
+        assert_eq!(Jitter::default().apply(interval), interval);
+        let jitter = Jitter::Fraction(0.1);
+        for _ in 0..100 {
+            let jittered = jitter.apply(interval);
+            assert!(jittered >= Duration::from_secs(27) && jittered <= Duration::from_secs(33));
+        }
+        // Even a jitter as long as the interval leaves at least half of it
+        for jitter in [
+            Jitter::Fraction(1.0),
+            Jitter::Absolute(Duration::from_secs(60)),
+        ] {
+            assert_eq!(jitter.bound(interval), Duration::from_secs(15));
+            for _ in 0..100 {
+                let jittered = jitter.apply(interval);
+                assert!(jittered > Duration::ZERO);
+                assert!(jittered >= Duration::from_secs(15) && jittered <= Duration::from_secs(45));
+            }
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+//! Reading the battery of wireless mice over HID
+//!
+//! [`config::Config`] describes the mice to look for, [`device`] finds and opens them,
+//! [`mouse::Mouse`] reads their battery and [`poll`] keeps track of each opened mouse between
+//! reads. The `nezumi` binary builds its daemon on top of this.
+pub mod config;
+pub mod device;
+pub mod duration;
+pub mod format;
+pub mod history;
+pub mod jitter;
+pub mod mouse;
+pub mod output;
+pub mod poll;
+pub mod template;
+pub mod thresholds;
@@ -1,116 +1,609 @@
-mod mouse;
+mod csv_log;
+mod dbus;
+mod metrics;
+mod mqtt;
+mod notify;
+mod socket;
+mod state;
+mod tui;
 
-use crate::mouse::Mouse;
-use clap::Parser;
+use crate::csv_log::CsvLog;
+use crate::metrics::Metrics;
+use crate::notify::LowBatteryNotifier;
+use crate::socket::StatusCache;
+use crate::state::StateFile;
+use crate::tui::Tui;
+use clap::{Parser, Subcommand};
+use directories::ProjectDirs;
 use futures_util::stream::StreamExt;
 use hex::FromHex;
-use hidapi::{HidApi, HidDevice};
+use hidapi::{DeviceInfo, HidApi};
 use linked_hash_map::LinkedHashMap;
-use std::fs;
-use std::io;
-use std::path::PathBuf;
+use nezumi::config::{Config, Interface, MouseProfile};
+use nezumi::device::{
+    lock_hid_api, open_all_mice, open_first_mouse, open_first_mouse_retrying, open_node,
+    open_profile, reopen, Node, OpenFirstMouseError, SharedHidApi,
+};
+use nezumi::duration;
+use nezumi::format::{self, Format, Formatter, Icons, Reading};
+use nezumi::jitter::Jitter;
+use nezumi::mouse::{self, Mouse, MouseState};
+use nezumi::output::{Output, OutputSink};
+use nezumi::poll::{poll_due, poll_once, remove_line, Events, OpenMouse, PollOptions, Schedule};
+use nezumi::template::{Template, DEFAULT_TEMPLATE};
+use nezumi::thresholds::Thresholds;
+use sd_notify::NotifyState;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::watch;
 use tokio::time::{self, Duration, Instant};
 use tokio_udev::{AsyncMonitorSocket, Event, EventType, MonitorBuilder};
-use tracing::{debug, error, info, warn, Level};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 use tracing_subscriber::FmtSubscriber;
 
 /// Daemon to monitor mouse battery status
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path to config
-    #[arg(short, long, default_value = "mouse.toml")]
-    config: PathBuf,
-    /// How long to wait each time we check the battery
-    #[arg(short, long, default_value_t = 30)]
-    interval: u64,
-}
-
-/// Profile describing a mouse
-#[derive(Debug, serde::Deserialize)]
-struct MouseProfile {
-    /// Model name of the mouse
-    model: String,
-    /// Product id
-    #[serde(deserialize_with = "deserialize_id")]
-    product: u16,
-    /// Vendor id
-    #[serde(deserialize_with = "deserialize_id")]
-    vendor: u16,
-    /// USB endpoint
-    endpoint: i32,
-}
-fn deserialize_id<'de, D>(deserializer: D) -> Result<u16, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let bytes: [u8; 2] = hex::serde::deserialize(deserializer)?;
-    Ok(u16::from_be_bytes(bytes))
-}
-
-fn open_first_mouse<'a>(
-    hid_api: &HidApi,
-    mice: impl Iterator<Item = (&'a String, &'a MouseProfile)>,
-) -> Result<Box<dyn Mouse>, OpenFirstMouseError> {
-    let mut mouse = None;
-    for (name, profile) in mice {
-        for cur_device in hid_api.device_list() {
-            if cur_device.vendor_id() == profile.vendor
-                && cur_device.product_id() == profile.product
-                && cur_device.interface_number() == profile.endpoint
-            {
-                info!("Found {name}");
-                let device = cur_device.open_device(hid_api)?;
-                let cur_mouse = mouse::get_mouse(&profile.model, device)?;
-                mouse = Some(cur_mouse);
-                break;
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Path to config [default: $XDG_CONFIG_HOME/nezumi/mouse.toml]
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Use a single mouse of this model instead of the profiles in the config, together with
+    /// --vendor, --product and --endpoint
+    #[arg(long, requires_all = ["vendor", "product", "endpoint"], conflicts_with = "config")]
+    model: Option<String>,
+    /// USB vendor id in hex of the --model mouse
+    #[arg(long, requires = "model", value_parser = parse_hex_id)]
+    vendor: Option<u16>,
+    /// USB product id in hex of the --model mouse
+    #[arg(long, requires = "model", value_parser = parse_hex_id)]
+    product: Option<u16>,
+    /// USB interface number of the --model mouse that reports the battery
+    #[arg(long, requires = "model")]
+    endpoint: Option<i32>,
+    /// How long to wait each time we check the battery, e.g. `30`, `5m` or `1h`. A bare number
+    /// is in seconds, and 0 is the same as --oneshot
+    #[arg(short, long, default_value = "30", value_parser = duration::parse)]
+    interval: Duration,
+    /// Randomly lengthen or shorten each interval by up to this much, as a percentage of the
+    /// interval such as `10%` or a duration such as `5s`, to keep several daemons from reading
+    /// at the same time. Never more than half the interval
+    #[arg(long, default_value = "0")]
+    jitter: Jitter,
+    /// Output format for battery readings
+    #[arg(short, long, value_enum, default_value_t = Format::Human)]
+    format: Format,
+    /// Template for human readable output, with placeholders {percent}, {charging}, {model},
+    /// {name} and {icon}. Use {{ and }} for literal braces
+    #[arg(long, default_value = DEFAULT_TEMPLATE)]
+    format_template: Template,
+    /// Printed by `--format percent` when the battery is unknown, e.g. `-1`. Nothing is printed
+    /// if unset
+    #[arg(long, value_name = "TEXT")]
+    percent_unknown: Option<String>,
+    /// Only print a reading when it differs from the previous one
+    #[arg(long)]
+    on_change: bool,
+    /// Show the next charging frame this often between battery reads, e.g. `1`, see
+    /// `charging_frames` in the `icons` config table
+    #[arg(long, value_parser = duration::parse)]
+    animation_interval: Option<Duration>,
+    /// Where to write battery readings: `-` for stdout, `fd:N` for an inherited file
+    /// descriptor, or a path
+    #[arg(short, long, default_value = "-")]
+    output: OutputSink,
+    /// Append to the --output file instead of rewriting it with each reading
+    #[arg(long)]
+    output_append: bool,
+    /// How long to wait for the mouse to respond, in milliseconds
+    #[arg(long, default_value_t = 200)]
+    read_timeout_ms: i32,
+    /// How long to wait for a mouse to connect before rescanning anyway, e.g. `300` or `5m`
+    #[arg(long, default_value = "300", value_parser = duration::parse)]
+    udev_timeout: Duration,
+    /// How long to wait for a newly connected mouse to settle before opening it, in milliseconds
+    #[arg(long, default_value_t = 500)]
+    udev_settle_ms: u64,
+    /// Don't watch udev, instead look for a missing mouse again every interval
+    #[arg(long)]
+    no_udev: bool,
+    /// How many times to retry a failed battery read before giving up on the mouse
+    #[arg(long, default_value_t = 3)]
+    read_retries: u32,
+    /// Append the mouse's DPI setting to the output, for models that support reading it
+    #[arg(long)]
+    show_dpi: bool,
+    /// Report the average percentage of the last N readings to hide jitter
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    smooth: usize,
+    /// Only report a change between charging and discharging once N readings in a row agree,
+    /// to ignore a flag flickering at the charge boundary
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    charging_reads: usize,
+    /// Poll every connected mouse in the config instead of only the first one found
+    #[arg(short, long)]
+    all_mice: bool,
+    /// How many times to retry initializing hidapi, once every interval, before giving up.
+    /// Retries forever if unset
+    #[arg(long, value_name = "N")]
+    hid_init_retries: Option<u32>,
+    /// How many more times --oneshot tries opening the mouse before giving up
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    open_retries: u32,
+    /// Print the battery status once and exit
+    ///
+    /// Exits with 69 if no mouse is connected, 70 if a connected mouse's model is not
+    /// supported, 77 if it may not be opened and 78 if the config is invalid or has no profiles
+    #[arg(long)]
+    oneshot: bool,
+    /// With --oneshot, read the battery up to N times and print the median of the readings
+    /// that came back, to ride out noisy wireless reads
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    count: usize,
+    /// Print the raw battery response of the first mouse found in hex and exit, for debugging
+    /// new models
+    #[arg(long)]
+    raw: bool,
+    /// Log filter directives, e.g. `warn` or `nezumi=debug`. Overrides `RUST_LOG`
+    #[arg(long)]
+    log_level: Option<String>,
+    /// Format of the log written to stderr
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Write an example config to the config path and exit
+    #[arg(long)]
+    generate_config: bool,
+    /// Check that the config parses and only uses supported models, then exit
+    #[arg(long)]
+    check_config: bool,
+    /// Show a desktop notification when the battery runs low, see the `thresholds` config table
+    #[arg(long)]
+    notify: bool,
+    /// Save the last reading of each mouse to $XDG_STATE_HOME/nezumi/state.json, and print it
+    /// marked as stale on startup until the mouse is read again. Readings older than
+    /// --stale-after aren't printed
+    #[arg(long)]
+    persist_state: bool,
+    /// Append every successful reading to this CSV file
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+    /// Serve the latest status of each mouse as JSON on this Unix socket
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// Keep publishing the last battery reading on the socket, metrics, MQTT and D-Bus while a
+    /// mouse is asleep or not answering, flagged as stale once it is older than this, e.g.
+    /// `10m`. Stale batteries are dropped from the metrics
+    #[arg(long, value_parser = duration::parse)]
+    stale_after: Option<Duration>,
+    /// Serve Prometheus metrics over HTTP on this address, e.g. `127.0.0.1:9115`
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Publish each battery reading to this MQTT broker, e.g. `localhost:1883`
+    ///
+    /// Mice are announced to Home Assistant through MQTT discovery
+    #[arg(long)]
+    mqtt_broker: Option<mqtt::Broker>,
+    /// Prefix of the MQTT topics published to
+    #[arg(long, default_value = "nezumi")]
+    mqtt_topic: String,
+    /// Client id to connect to the MQTT broker with [default: the topic prefix and process id]
+    ///
+    /// The broker drops a client when another one connects with the same id
+    #[arg(long)]
+    mqtt_client_id: Option<String>,
+    /// QoS of published MQTT messages
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=2))]
+    mqtt_qos: u8,
+    /// Have the broker retain battery readings
+    #[arg(long)]
+    mqtt_retain: bool,
+    /// Export the battery of each mouse on the D-Bus session bus
+    #[arg(long)]
+    dbus: bool,
+    /// Show a live display of each mouse's battery in the terminal instead of printing
+    /// readings, with a sparkline of the recent history. Press q to quit
+    ///
+    /// Nothing is logged unless asked to with --log-level or `RUST_LOG`
+    #[arg(
+        long,
+        conflicts_with_all = ["oneshot", "raw", "format", "output", "animation_interval"]
+    )]
+    watch: bool,
+    /// Exit after this many successful battery reads, for testing
+    #[arg(long, hide = true, value_parser = clap::value_parser!(u64).range(1..))]
+    max_iterations: Option<u64>,
+}
+
+/// Format of the log written to stderr
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List connected HID devices, to help write a profile
+    List {
+        /// Include devices that are not mice
+        #[arg(short, long)]
+        all: bool,
+    },
+    /// Print the model, serial number and firmware version of the first mouse found
+    Info,
+    /// Write raw bytes to a HID device and dump the response in hex, to help bring up a new
+    /// model. No config is needed
+    Probe {
+        /// USB vendor id in hex
+        #[arg(long, value_parser = parse_hex_id)]
+        vendor: u16,
+        /// USB product id in hex
+        #[arg(long, value_parser = parse_hex_id)]
+        product: u16,
+        /// USB interface number, the first interface found if unset
+        #[arg(long)]
+        endpoint: Option<i32>,
+        /// Bytes to write in hex separated by commas, starting with the report id, e.g. `00,92`
+        #[arg(long, required = true, value_delimiter = ',', value_parser = parse_hex_byte)]
+        write: Vec<u8>,
+        /// Maximum number of bytes to read back
+        #[arg(long, default_value_t = 64)]
+        read_len: usize,
+    },
+}
+
+/// Parses a USB id given in hex on the command line
+fn parse_hex_id(id: &str) -> Result<u16, String> {
+    parse_udev_id(id).ok_or_else(|| "expected up to 4 hex digits".to_owned())
+}
+
+/// Parses a byte given in hex on the command line
+fn parse_hex_byte(byte: &str) -> Result<u8, String> {
+    u8::from_str_radix(byte.trim(), 16).map_err(|_| "expected up to 2 hex digits".to_owned())
+}
+
+/// Config written by --generate-config
+const EXAMPLE_CONFIG: &str = r#"# Each table is a profile describing a mouse. Profiles are tried in order and the first
+# connected mouse is used.
+# The table named `icons` is reserved for the glyphs used in human readable output, e.g.
+# [icons]
+# ramp = [{ min = 0, icon = "\uf244" }, { min = 50, icon = "\uf242" }, { min = 88, icon = "\uf240" }]
+# charging_bolt = true
+# Shown instead of the bolt once the battery reaches the full threshold while still charging
+# charged = "\uf1e6"
+# Shown in turn instead of the bolt while charging, one per reading or `--animation-interval`
+# charging_frames = ["\uf244", "\uf243", "\uf242", "\uf241", "\uf240"]
+# The table named `thresholds` is reserved for the battery percentages at or below which the
+# battery is low or critical, and at or above which it is full. These style waybar output and
+# trigger `--notify`, e.g.
+# [thresholds]
+# critical = 10
+# low = 25
+# full = 100
+[wireless]
+# Model of the mouse, run `nezumi --generate-config` to list the supported models
+model = "steelseries_aerox_9_wireless"
+# USB vendor and product id, either as a string of 4 hex digits or a decimal number.
+# Bare numbers are always decimal, so `product = 6232` is the same as `product = "1858"`
+vendor = "1038"
+product = "1858"
+# USB interface number that reports the battery, or a list of them to try in order, e.g.
+# `endpoint = [3, 4]`
+endpoint = 3
+# Alternatively the interface can be picked by its HID usage, or pinned to a device node
+# usage_page = 0xffc0
+# usage = 1
+# path = "/dev/hidraw3"
+# Serial number of the mouse, needed to tell several identical mice apart
+# serial = "0123456789ab"
+# Read the battery from a feature report when the mouse doesn't answer, which some Aerox 9
+# Wireless firmware revisions need
+# feature_report_fallback = true
+# Advanced, for bringing up firmware variants: which byte of the Aerox 9's response holds the
+# battery, overriding the built in offset of 1
+# response_offset = 2
+# Advanced: report id written before each request to an Aerox, instead of 0. "none" writes
+# requests without one
+# report_id = 2
+# Advanced: bits of an Aerox 9's battery byte set while charging and of its request asking the
+# receiver, overriding the built in 0x80 and 0x40
+# charging_flag = 0x80
+# wireless_flag = 0x40
+# Seconds between battery checks, defaults to `--interval`
+# interval = 60
+# Commands run when the mouse starts or stops charging, with the percentage appended
+# on_charge_start = ["notify-send", "Mouse charging"]
+# on_charge_stop = ["notify-send", "Mouse unplugged"]
+"#;
+
+/// Name of the config file looked for in the user's config directory
+const CONFIG_FILE: &str = "mouse.toml";
+
+/// Where --persist-state keeps the last reading of each mouse, `None` without a home directory
+fn state_path() -> Option<PathBuf> {
+    let dirs = ProjectDirs::from("", "", "nezumi")?;
+    Some(dirs.state_dir()?.join("state.json"))
+}
+
+/// Picks the config file, preferring an explicit path over the user's config directory
+///
+/// Falls back to `mouse.toml` in the working directory if there is no home directory
+fn config_path(explicit: Option<&Path>) -> PathBuf {
+    match explicit {
+        Some(path) => path.to_owned(),
+        None => ProjectDirs::from("", "", "nezumi").map_or_else(
+            || PathBuf::from(CONFIG_FILE),
+            |dirs| dirs.config_dir().join(CONFIG_FILE),
+        ),
+    }
+}
+
+/// Writes [`EXAMPLE_CONFIG`] to `path`, refusing to overwrite an existing file
+fn generate_config(path: &Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(Error::WriteConfig)?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => Error::ConfigExists(path.to_owned()),
+            _ => Error::WriteConfig(err),
+        })?;
+    file.write_all(EXAMPLE_CONFIG.as_bytes())
+        .map_err(Error::WriteConfig)?;
+    println!("Wrote example config to {}", path.display());
+    println!("Supported models:");
+    for model in mouse::supported_models() {
+        println!("    {model}");
+    }
+    Ok(())
+}
+
+/// Prints details of the first connected mouse in the config, for support requests
+fn print_info(hid_api: &HidApi, mouse_config: &Config, read_timeout: i32) -> Result<(), Error> {
+    let Some((name, profile, device_info)) =
+        mouse_config.profiles.iter().find_map(|(name, profile)| {
+            hid_api
+                .device_list()
+                .find(|device| profile.matches(device))
+                .map(|device| (name, profile, device))
+        })
+    else {
+        return Err(OpenFirstMouseError::NotFound.into());
+    };
+    let device = device_info
+        .open_device(hid_api)
+        .map_err(OpenFirstMouseError::OpenMouse)?;
+    let mouse = mouse::get_mouse(
+        &profile.model,
+        device,
+        &profile.driver_options(read_timeout),
+    )
+    .map_err(OpenFirstMouseError::WrapMouse)?;
+    println!("Profile: {name}");
+    println!("Model: {}", profile.model);
+    println!(
+        "Device: {} {}",
+        device_info.manufacturer_string().unwrap_or("(unknown)"),
+        device_info.product_string().unwrap_or("(unknown)")
+    );
+    println!(
+        "Serial: {}",
+        device_info
+            .serial_number()
+            .filter(|serial| !serial.is_empty())
+            .unwrap_or("(unknown)")
+    );
+    match mouse.firmware_version().map_err(Error::ReadFirmware)? {
+        Some(version) => println!("Firmware: {version}"),
+        // The USB device release usually tracks the firmware
+        None => {
+            let release = device_info.release_number();
+            println!(
+                "Firmware: {:x}.{:02x} (USB device release)",
+                release >> 8,
+                release & 0xff
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Opens the mice to poll, every connected one with `--all-mice` or else the first
+async fn open_mice<'a>(
+    hid_api: &SharedHidApi,
+    mouse_config: &'a Config,
+    args: &Args,
+) -> Vec<OpenMouse<'a>> {
+    let profiles = mouse_config.profiles.iter();
+    if args.all_mice {
+        let mice = open_all_mice(hid_api, profiles, args.read_timeout_ms).await;
+        if mice.is_empty() {
+            error!("No mouse found");
+        }
+        mice
+    } else {
+        match open_first_mouse(hid_api, profiles, args.read_timeout_ms).await {
+            Ok(mouse) => vec![mouse],
+            Err(err) => {
+                error!("Error opening first mouse: {err}");
+                Vec::new()
             }
         }
     }
-    mouse.ok_or(OpenFirstMouseError::NotFound)
 }
-#[derive(Debug, thiserror::Error)]
-enum OpenFirstMouseError {
-    #[error("No mouse found")]
-    NotFound,
-    #[error("Error opening the found mouse: {0}")]
-    OpenMouse(#[from] hidapi::HidError),
-    #[error("Error wrapping the mouse device: {0}")]
-    WrapMouse(#[from] crate::mouse::GetMouseError),
+
+/// Opens a mouse that connected while others are being polled
+///
+/// Waits for the device to settle first. Returns `None` if its HID interface isn't there (yet)
+async fn open_connected<'a>(
+    hid_api: &SharedHidApi,
+    mouse_config: &'a Config,
+    name: &'a String,
+    args: &Args,
+    settle: Duration,
+) -> Option<OpenMouse<'a>> {
+    let profile = mouse_config.profiles.get(name)?;
+    time::sleep(settle).await;
+    if let Err(err) = lock_hid_api(hid_api).refresh_devices() {
+        error!("Error refreshing HID device list: {err}");
+        return None;
+    }
+    match open_profile(hid_api, name, profile, args.read_timeout_ms).await {
+        Ok(Some((mouse, node))) => {
+            let mut open_mouse = OpenMouse::new(name, profile, mouse);
+            open_mouse.node = Some(node);
+            Some(open_mouse)
+        }
+        Ok(None) => None,
+        Err(err) => {
+            error!("Error opening {name}: {err}");
+            None
+        }
+    }
 }
 
+/// What the polling loop does with each read, besides printing it
+struct LoopEvents<'l> {
+    hid_api: &'l SharedHidApi,
+    read_timeout: i32,
+    exports: &'l Exports,
+    tui: &'l mut Option<Tui>,
+    /// Whether to ping the systemd watchdog after each read
+    watchdog: bool,
+    /// Whether the readings saved by the last run are still shown
+    showing_saved: &'l mut bool,
+    state_file: &'l mut Option<StateFile>,
+    /// Low battery notification state of each profile, with --notify
+    notifiers: Option<&'l mut HashMap<String, LowBatteryNotifier>>,
+    thresholds: Thresholds,
+    csv_log: &'l mut Option<CsvLog>,
+    /// Device node each mouse was last opened on, by profile name
+    nodes: &'l mut HashMap<String, Node>,
+}
+impl Events for LoopEvents<'_> {
+    fn record(&mut self, open_mouse: &mut OpenMouse<'_>, state: MouseState) {
+        self.exports.record(open_mouse, state, &self.thresholds);
+        if let Some(tui) = self.tui {
+            tui.record(&open_mouse.reading(state));
+        }
+        if self.watchdog {
+            if let Err(err) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                warn!("Error pinging systemd watchdog: {err}");
+            }
+        }
+        let MouseState::Battery(status) = state else {
+            return;
+        };
+        *self.showing_saved = false;
+        open_mouse.check_charging(&status);
+        if let Some(state_file) = self.state_file {
+            if let Err(err) = state_file.record(open_mouse.name, &status) {
+                error!("Error writing state file: {err}");
+            }
+        }
+        if let Some(notifiers) = &mut self.notifiers {
+            notifiers
+                .entry(open_mouse.name.to_owned())
+                .or_insert_with(|| LowBatteryNotifier::new(self.thresholds))
+                .update(open_mouse.name, &status);
+        }
+        if let Some(csv_log) = self.csv_log {
+            if let Err(err) = csv_log.write(&open_mouse.profile.model, &status) {
+                error!("Error writing to log file: {err}");
+            }
+        }
+    }
+
+    fn read_error(&mut self, _open_mouse: &OpenMouse<'_>, _err: &hidapi::HidError) {
+        self.exports.read_error();
+    }
+
+    async fn reopen(&mut self, open_mouse: &OpenMouse<'_>) -> Option<(Box<dyn Mouse>, Node)> {
+        reopen(self.hid_api, open_mouse, self.read_timeout).await
+    }
+
+    fn removed(&mut self, open_mouse: &OpenMouse<'_>) {
+        if let Some(node) = &open_mouse.node {
+            self.nodes.insert(open_mouse.name.to_owned(), node.clone());
+        }
+        self.exports.remove(open_mouse.name);
+        if let Some(tui) = self.tui {
+            tui.remove(open_mouse.name);
+        }
+    }
+}
+
+/// Change to one of our mice reported by udev
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeviceChange {
+    Connected,
+    Disconnected,
+}
+
+/// Parses a USB id as udev reports it, tolerating missing zero padding and trailing whitespace
+fn parse_udev_id(id: &str) -> Option<u16> {
+    let id = id.trim();
+    if id.is_empty() || id.len() > 4 {
+        return None;
+    }
+    <[u8; 2]>::from_hex(format!("{id:0>4}"))
+        .map(u16::from_be_bytes)
+        .ok()
+}
+
+/// Checks whether a udev event concerns one of our mice
+///
+/// Returns the name of the first matching profile along with what happened to the device
 fn process_udev_event<'a>(
     event: &Event,
     mice: impl Iterator<Item = (&'a String, &'a MouseProfile)>,
-) -> Result<bool, UdevEventError> {
-    if event.event_type() == EventType::Bind {
-        let device = event.device();
-        let vendor_id = device
-            .attribute_value("idVendor")
-            .ok_or(UdevEventError::MissingVendor)?
-            .to_str()
-            .ok_or(UdevEventError::InvalidVendor)?;
-        let vendor_id = <[u8; 2]>::from_hex(vendor_id)
-            .map(u16::from_be_bytes)
-            .map_err(|_| UdevEventError::InvalidVendor)?;
-        let product_id = device
-            .attribute_value("idProduct")
-            .ok_or(UdevEventError::MissingProduct)?
-            .to_str()
-            .ok_or(UdevEventError::InvalidProduct)?;
-        let product_id = <[u8; 2]>::from_hex(product_id)
-            .map(u16::from_be_bytes)
-            .map_err(|_| UdevEventError::InvalidProduct)?;
-        for (name, profile) in mice {
-            if profile.vendor == vendor_id && profile.product == product_id {
-                info!("Device {name} has been connected");
-                return Ok(true);
-            }
-        }
-        Ok(false)
-    } else {
-        Ok(false)
+) -> Result<Option<(&'a String, DeviceChange)>, UdevEventError> {
+    let change = match event.event_type() {
+        EventType::Bind => DeviceChange::Connected,
+        EventType::Unbind | EventType::Remove => DeviceChange::Disconnected,
+        _ => return Ok(None),
+    };
+    let device = event.device();
+    // Removed devices no longer have sysfs attributes, but udev still reports their properties
+    let vendor_id = device
+        .attribute_value("idVendor")
+        .or_else(|| device.property_value("ID_VENDOR_ID"))
+        .ok_or(UdevEventError::MissingVendor)?
+        .to_str()
+        .ok_or(UdevEventError::InvalidVendor)?;
+    let vendor_id = parse_udev_id(vendor_id).ok_or(UdevEventError::InvalidVendor)?;
+    let product_id = device
+        .attribute_value("idProduct")
+        .or_else(|| device.property_value("ID_MODEL_ID"))
+        .ok_or(UdevEventError::MissingProduct)?
+        .to_str()
+        .ok_or(UdevEventError::InvalidProduct)?;
+    let product_id = parse_udev_id(product_id).ok_or(UdevEventError::InvalidProduct)?;
+    for (name, profile) in mice {
+        if profile.vendor == vendor_id && profile.product == product_id {
+            match change {
+                DeviceChange::Connected => info!("Device {name} has been connected"),
+                DeviceChange::Disconnected => info!("Device {name} has been disconnected"),
+            }
+            return Ok(Some((name, change)));
+        }
     }
+    Ok(None)
 }
 #[derive(Debug, thiserror::Error)]
 enum UdevEventError {
@@ -124,84 +617,951 @@ enum UdevEventError {
     InvalidProduct,
 }
 
+/// Resolves when the daemon is asked to stop
+struct Shutdown {
+    sigint: Signal,
+    sigterm: Signal,
+}
+impl Shutdown {
+    fn new() -> io::Result<Self> {
+        Ok(Shutdown {
+            sigint: signal(SignalKind::interrupt())?,
+            sigterm: signal(SignalKind::terminate())?,
+        })
+    }
+
+    /// Waits for SIGINT or SIGTERM
+    async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.sigint.recv() => info!("Received SIGINT, shutting down"),
+            _ = self.sigterm.recv() => info!("Received SIGTERM, shutting down"),
+        }
+    }
+}
+
+/// HID usage page for generic desktop controls
+const USAGE_PAGE_GENERIC_DESKTOP: u16 = 0x01;
+/// HID usage for a mouse on the generic desktop page
+const USAGE_MOUSE: u16 = 0x02;
+
+/// Prints every interface of every connected mouse
+///
+/// Interfaces are listed for any device that has a mouse interface, since the battery is usually
+/// reported on a vendor defined one. Interfaces matching a profile are marked with its name
+fn list_devices(hid_api: &HidApi, mouse_config: Option<&Config>, all: bool) {
+    let is_mouse = |device: &DeviceInfo| {
+        hid_api.device_list().any(|other| {
+            other.vendor_id() == device.vendor_id()
+                && other.product_id() == device.product_id()
+                && other.usage_page() == USAGE_PAGE_GENERIC_DESKTOP
+                && other.usage() == USAGE_MOUSE
+        })
+    };
+    for device in hid_api.device_list() {
+        let profile = mouse_config.and_then(|mouse_config| {
+            mouse_config
+                .profiles
+                .iter()
+                .find(|(_, profile)| profile.matches(device))
+        });
+        if !all && profile.is_none() && !is_mouse(device) {
+            continue;
+        }
+        println!(
+            "{} {:04x}:{:04x} interface {} usage {:04x}:{:04x} {} {}{}",
+            if profile.is_some() { "*" } else { " " },
+            device.vendor_id(),
+            device.product_id(),
+            device.interface_number(),
+            device.usage_page(),
+            device.usage(),
+            device.manufacturer_string().unwrap_or("(unknown)"),
+            device.product_string().unwrap_or("(unknown)"),
+            profile
+                .map(|(name, _)| format!(" [{name}]"))
+                .unwrap_or_default(),
+        );
+    }
+}
+
+/// Writes bytes to the matching interface and prints whatever it answers with
+fn probe(
+    hid_api: &HidApi,
+    (vendor, product): (u16, u16),
+    endpoint: Option<i32>,
+    write: &[u8],
+    read_len: usize,
+    read_timeout: i32,
+) -> Result<(), Error> {
+    let device_info = hid_api
+        .device_list()
+        .find(|device| {
+            device.vendor_id() == vendor
+                && device.product_id() == product
+                && endpoint.is_none_or(|endpoint| device.interface_number() == endpoint)
+        })
+        .ok_or(Error::DeviceNotFound)?;
+    println!(
+        "Opening {vendor:04x}:{product:04x} interface {} at {}",
+        device_info.interface_number(),
+        device_info.path().to_string_lossy()
+    );
+    let device = device_info.open_device(hid_api).map_err(Error::Probe)?;
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let start = Instant::now();
+    let written = device.write(write).map_err(Error::Probe)?;
+    println!(
+        "Wrote {written} bytes in {:?}: {}",
+        start.elapsed(),
+        hex(write)
+    );
+    let start = Instant::now();
+    let mut response = vec![0; read_len];
+    let len = device
+        .read_timeout(&mut response, read_timeout)
+        .map_err(Error::Probe)?;
+    if len == 0 {
+        println!("No response within {read_timeout} ms");
+    } else {
+        println!(
+            "Read {len} bytes in {:?}: {}",
+            start.elapsed(),
+            hex(&response[..len])
+        );
+    }
+    Ok(())
+}
+
+/// Loads the profiles from the config file, or makes one up from --model
+fn load_profiles(args: &Args, path: &Path) -> Result<Config, Error> {
+    let (Some(model), Some(vendor), Some(product), Some(endpoint)) =
+        (&args.model, args.vendor, args.product, args.endpoint)
+    else {
+        return load_config(path);
+    };
+    let profile = MouseProfile {
+        model: model.clone(),
+        product,
+        vendor,
+        interface: Interface::Endpoint(vec![endpoint]),
+        serial: None,
+        feature_report_fallback: false,
+        response_offset: None,
+        report_id: None,
+        charging_flag: None,
+        wireless_flag: None,
+        interval: None,
+        on_charge_start: None,
+        on_charge_stop: None,
+    };
+    let mut profiles = LinkedHashMap::new();
+    profiles.insert(model.clone(), profile);
+    Ok(Config {
+        icons: Icons::default(),
+        thresholds: Thresholds::default(),
+        profiles,
+    })
+}
+
+/// Loads the profiles from the config file, failing if it has none since there would be no
+/// mouse to ever look for
+fn load_config(path: &Path) -> Result<Config, Error> {
+    let mouse_config = fs::read(path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => Error::ConfigNotFound(path.to_owned()),
+        _ => Error::OpenConfig(err),
+    })?;
+    let mouse_config = String::from_utf8(mouse_config).map_err(Error::ConfigNotUtf8)?;
+    // Syntax errors keep their position, while errors in the contents go through a value
+    // instead, since the parser would place them all at the start of the flattened profiles
+    let mouse_config: Config = toml::from_str::<toml::Value>(&mouse_config)?.try_into()?;
+    if mouse_config.profiles.is_empty() {
+        return Err(Error::NoProfiles(path.to_owned()));
+    }
+    Ok(mouse_config)
+}
+
+/// Checks that every profile uses a supported model, printing a line per profile
+fn check_config(mouse_config: &Config) -> Result<(), Error> {
+    for (name, profile) in &mouse_config.profiles {
+        if mouse::supported_models().any(|model| model == profile.model) {
+            println!("{name}: {}", profile.model);
+        } else {
+            println!("{name}: unknown model {}", profile.model);
+        }
+    }
+    check_models(mouse_config)?;
+    println!("Config is valid, {} profiles", mouse_config.profiles.len());
+    Ok(())
+}
+
+/// Fails if any profile names a model that isn't supported
+///
+/// Flag overrides that aren't a single bit are only warned about, since probing a new model may
+/// take odd values
+fn check_models(mouse_config: &Config) -> Result<(), Error> {
+    for (name, profile) in &mouse_config.profiles {
+        let flags = [
+            ("charging_flag", profile.charging_flag),
+            ("wireless_flag", profile.wireless_flag),
+        ];
+        for (field, flag) in flags {
+            if let Some(flag) = flag.filter(|flag| flag.count_ones() != 1) {
+                warn!("{name}: {field} {flag:#04x} is not a single bit");
+            }
+        }
+    }
+    let unknown: Vec<&str> = mouse_config
+        .profiles
+        .iter()
+        .filter(|(_, profile)| !mouse::supported_models().any(|model| model == profile.model))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::UnknownModels(unknown.join(", ")))
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Places other than stdout that the latest status of each mouse is published to
+#[derive(Default)]
+struct Exports {
+    /// Cache served on the status socket
+    status_cache: Option<watch::Sender<StatusCache>>,
+    /// Metrics served to Prometheus
+    metrics: Option<Arc<Mutex<Metrics>>>,
+    /// Readings published over MQTT
+    mqtt: Option<mqtt::Publisher>,
+    /// Batteries exported on D-Bus
+    dbus: Option<dbus::Service>,
+    /// How old the last battery reading of a mouse that stopped answering may get before it
+    /// is stale, `None` to publish the state as read
+    stale_after: Option<Duration>,
+}
+impl Exports {
+    /// What to publish for a read, holding on to the last battery reading with --stale-after
+    ///
+    /// Returns the state along with whether it is stale
+    fn held(&self, open_mouse: &OpenMouse, state: MouseState) -> (MouseState, bool) {
+        match (state, self.stale_after, open_mouse.last_battery()) {
+            (
+                MouseState::Asleep | MouseState::NoResponse,
+                Some(stale_after),
+                Some((at, status)),
+            ) => (MouseState::Battery(status), at.elapsed() > stale_after),
+            _ => (state, false),
+        }
+    }
+
+    /// Publishes the result of a successful read, classified by `thresholds` where needed
+    fn record(&self, open_mouse: &OpenMouse, read: MouseState, thresholds: &Thresholds) {
+        let (state, stale) = self.held(open_mouse, read);
+        let reading = Reading {
+            stale,
+            ..open_mouse.reading(state)
+        };
+        if let Some(status_cache) = &self.status_cache {
+            match format::json(&reading) {
+                Ok(line) => status_cache.send_modify(|cache| {
+                    cache.insert(open_mouse.name.to_owned(), line);
+                }),
+                Err(err) => error!("Error formatting battery status: {err}"),
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            let mut metrics = metrics.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some(duration) = open_mouse.read_duration() {
+                metrics.read_duration(open_mouse.name, &open_mouse.profile.model, duration);
+            }
+            if read == MouseState::NoResponse {
+                metrics.no_response();
+            }
+            match state {
+                // Dropping stale batteries lets Prometheus mark their series stale
+                MouseState::Battery(_) if stale => metrics.remove(open_mouse.name),
+                MouseState::Battery(status) => {
+                    metrics.set(open_mouse.name, &open_mouse.profile.model, status)
+                }
+                // The battery is unknown rather than empty while the mouse is off
+                MouseState::Asleep => metrics.remove(open_mouse.name),
+                MouseState::NoResponse => {}
+            }
+        }
+        if let Some(mqtt) = &self.mqtt {
+            match state {
+                MouseState::Battery(_) => match format::json(&reading) {
+                    Ok(json) => {
+                        mqtt.availability(open_mouse.name, !stale);
+                        mqtt.state(open_mouse.name, json);
+                    }
+                    Err(err) => error!("Error formatting battery status: {err}"),
+                },
+                MouseState::Asleep => mqtt.availability(open_mouse.name, false),
+                MouseState::NoResponse => {}
+            }
+        }
+        if let Some(dbus) = &self.dbus {
+            match state {
+                MouseState::Battery(status) => {
+                    let level = thresholds.classify(status.percent, status.is_charging);
+                    dbus.set(
+                        open_mouse.name,
+                        &open_mouse.profile.model,
+                        status,
+                        level,
+                        stale,
+                    )
+                }
+                MouseState::Asleep => dbus.remove(open_mouse.name),
+                MouseState::NoResponse => {}
+            }
+        }
+    }
+
+    /// Counts a read that failed with an error
+    fn read_error(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .read_error();
+        }
+    }
+
+    /// Stops publishing the status of a mouse that is gone
+    fn remove(&self, name: &str) {
+        if let Some(status_cache) = &self.status_cache {
+            status_cache.send_modify(|cache| {
+                cache.remove(name);
+            });
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .remove(name);
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.availability(name, false);
+        }
+        if let Some(dbus) = &self.dbus {
+            dbus.remove(name);
+        }
+    }
+}
+
+/// Initializes hidapi, trying again every `delay` up to `retries` times, or forever if `None`
+async fn init_hid_api(delay: Duration, retries: Option<u32>) -> Result<HidApi, Error> {
+    let mut attempt = 0;
+    loop {
+        match HidApi::new() {
+            Err(err) if retries.is_none_or(|retries| attempt < retries) => {
+                error!("Error initializing hidapi, retrying in {delay:?}: {err}");
+                time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result.map_err(Error::InitializeHidApi),
+        }
+    }
+}
+
+/// Watches udev for USB devices connecting and disconnecting
+fn udev_monitor() -> Result<AsyncMonitorSocket, Error> {
+    MonitorBuilder::new()
+        .map_err(Error::UdevBuildMonitor)?
+        .match_subsystem_devtype("usb", "usb_device")
+        .map_err(Error::UdevBuildMonitor)?
+        .listen()
+        .map_err(Error::UdevListen)?
+        .try_into()
+        .map_err(Error::UdevAsync)
+}
+
+/// Waits for the next udev event, forever if udev isn't watched
+async fn next_udev_event(monitor: &mut Option<AsyncMonitorSocket>) -> Option<io::Result<Event>> {
+    match monitor {
+        Some(monitor) => monitor.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for the next charging animation frame, never if there is no animation
+async fn next_frame(animation: &mut Option<time::Interval>) {
+    match animation {
+        Some(animation) => {
+            animation.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for the user to quit the --watch display, forever if there is none
+async fn quit(tui: &mut Option<Tui>) {
+    match tui {
+        Some(tui) => tui.quit().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Logs to stderr as configured on the command line
+fn init_logging(args: &Args) -> Result<(), Error> {
+    // Log at info unless told otherwise by the CLI or `RUST_LOG`, and not at all over the
+    // --watch display
+    let default_level = if args.watch {
+        LevelFilter::OFF
+    } else {
+        LevelFilter::INFO
+    };
+    let filter = EnvFilter::builder().with_default_directive(default_level.into());
+    let filter = match &args.log_level {
+        Some(directives) => filter.parse_lossy(directives),
+        None => filter.from_env_lossy(),
+    };
+    let subscriber = FmtSubscriber::builder()
+        .with_env_filter(filter)
+        .with_writer(io::stderr);
+    match args.log_format {
+        LogFormat::Text => tracing::subscriber::set_global_default(subscriber.finish())?,
+        LogFormat::Json => tracing::subscriber::set_global_default(subscriber.json().finish())?,
+    }
+    Ok(())
+}
+
+/// Runs a subcommand
+fn run_command(args: &Args, command: &Command, config_path: &Path) -> Result<(), Error> {
+    match *command {
+        Command::List { all } => {
+            // The config is only needed to mark known devices
+            let mouse_config = load_profiles(args, config_path)
+                .map_err(|err| warn!("Not marking known devices: {err}"))
+                .ok();
+            let hid_api = HidApi::new().map_err(Error::InitializeHidApi)?;
+            list_devices(&hid_api, mouse_config.as_ref(), all);
+            Ok(())
+        }
+        Command::Probe {
+            vendor,
+            product,
+            endpoint,
+            ref write,
+            read_len,
+        } => {
+            let hid_api = HidApi::new().map_err(Error::InitializeHidApi)?;
+            probe(
+                &hid_api,
+                (vendor, product),
+                endpoint,
+                write,
+                read_len,
+                args.read_timeout_ms,
+            )
+        }
+        Command::Info => {
+            let mouse_config = load_profiles(args, config_path)?;
+            check_models(&mouse_config)?;
+            let hid_api = HidApi::new().map_err(Error::InitializeHidApi)?;
+            print_info(&hid_api, &mouse_config, args.read_timeout_ms)
+        }
+    }
+}
+
+/// Prints the raw battery response of the first mouse found
+async fn print_raw(
+    hid_api: &SharedHidApi,
+    mouse_config: &Config,
+    read_timeout: i32,
+) -> Result<(), Error> {
+    let open_mouse = open_first_mouse(hid_api, mouse_config.profiles.iter(), read_timeout).await?;
+    let raw = open_mouse
+        .with_mouse(|mouse| mouse.battery_raw())
+        .await
+        .map_err(Error::ReadBattery)?;
+    let response: Vec<String> = raw
+        .response
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    println!(
+        "{}: response [{}], decoded {:?}",
+        open_mouse.name,
+        response.join(" "),
+        raw.status
+    );
+    Ok(())
+}
+
+/// Prints the battery status of the first mouse found once
+async fn run_oneshot(
+    hid_api: &SharedHidApi,
+    mouse_config: &Config,
+    args: &Args,
+    poll_options: &PollOptions,
+    formatter: &Formatter,
+    output: &mut Output,
+    csv_log: Option<&mut CsvLog>,
+) -> Result<(), Error> {
+    let mut mouse = open_first_mouse_retrying(
+        hid_api,
+        mouse_config,
+        args.read_timeout_ms,
+        args.open_retries,
+    )
+    .await?;
+    let poll_options = PollOptions {
+        on_change: false,
+        ..*poll_options
+    };
+    let state = poll_once(&mut mouse, &poll_options, args.count)
+        .await
+        .map_err(Error::ReadBattery)?;
+    mouse.emit(state, formatter, output, &poll_options);
+    match state {
+        MouseState::Battery(status) => {
+            if let Some(csv_log) = csv_log {
+                csv_log
+                    .write(&mouse.profile.model, &status)
+                    .map_err(Error::WriteLogFile)?;
+            }
+            Ok(())
+        }
+        MouseState::Asleep | MouseState::NoResponse => Err(Error::NoBatteryStatus),
+    }
+}
+
+/// Starts publishing the status of each mouse wherever the command line asks for
+async fn start_exports(args: &Args, mouse_config: &Config) -> Result<Exports, Error> {
+    let mut exports = Exports {
+        stale_after: args.stale_after,
+        ..Exports::default()
+    };
+    // Serve the latest status of each mouse to socket clients
+    if let Some(path) = &args.socket {
+        let listener = socket::bind(path).map_err(|err| Error::BindSocket(path.clone(), err))?;
+        let (sender, receiver) = watch::channel(StatusCache::new());
+        tokio::spawn(socket::serve(listener, receiver));
+        exports.status_cache = Some(sender);
+    }
+    // Serve Prometheus metrics
+    if let Some(addr) = args.metrics_addr {
+        let metrics = Arc::default();
+        metrics::serve(addr, Arc::clone(&metrics)).map_err(|err| Error::BindMetrics(addr, err))?;
+        exports.metrics = Some(metrics);
+    }
+    // Publish to MQTT, announcing every profile up front so Home Assistant knows about mice
+    // that aren't connected yet
+    if let Some(broker) = &args.mqtt_broker {
+        let client_id = args
+            .mqtt_client_id
+            .clone()
+            .unwrap_or_else(|| mqtt::default_client_id(&args.mqtt_topic));
+        let mqtt = mqtt::Publisher::connect(
+            broker,
+            &client_id,
+            args.mqtt_topic.clone(),
+            mqtt::qos(args.mqtt_qos),
+            args.mqtt_retain,
+        );
+        for (name, profile) in &mouse_config.profiles {
+            mqtt.announce(name, &profile.model);
+            mqtt.availability(name, false);
+        }
+        exports.mqtt = Some(mqtt);
+    }
+    // Export batteries on D-Bus
+    if args.dbus {
+        exports.dbus = Some(dbus::Service::start().await.map_err(Error::StartDbus)?);
+    }
+    Ok(exports)
+}
+
+/// Prints the readings saved by the last run flagged as stale, returning whether any was printed
+///
+/// Readings saved longer ago than `stale_after` are too old to show
+fn show_saved(
+    state_file: &StateFile,
+    mouse_config: &Config,
+    all_mice: bool,
+    stale_after: Option<Duration>,
+    formatter: &Formatter,
+    output: &mut Output,
+) -> bool {
+    let mut shown = false;
+    let saved = mouse_config
+        .profiles
+        .iter()
+        .filter_map(|(name, profile)| Some((name, profile, state_file.get(name)?)))
+        .filter(|(name, _, saved)| {
+            let fresh = stale_after.is_none_or(|stale_after| saved.age() <= stale_after);
+            if !fresh {
+                debug!(
+                    "Not showing the saved reading of {name}, it is {:?} old",
+                    saved.age()
+                );
+            }
+            fresh
+        })
+        .take(if all_mice { usize::MAX } else { 1 });
+    for (name, profile, saved) in saved {
+        let reading = Reading {
+            name,
+            model: &profile.model,
+            state: MouseState::Battery(saved.status()),
+            time_remaining: None,
+            dpi: None,
+            connection: None,
+            stale: true,
+            frame: 0,
+        };
+        match formatter.format(&reading) {
+            Ok(Some(line)) => {
+                shown = true;
+                if let Err(err) = output.line(&line) {
+                    error!("Error writing battery status: {err}");
+                }
+            }
+            Ok(None) => {}
+            Err(err) => error!("Error formatting battery status: {err}"),
+        }
+    }
+    shown
+}
+
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Error> {
+async fn run() -> Result<(), Error> {
     // Parse CLI args
     let args = Args::parse();
-    // Initialize a logger
-    // a builder for `FmtSubscriber`.
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::TRACE)
-        .with_writer(io::stderr)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+    init_logging(&args)?;
+    let config_path = config_path(args.config.as_deref());
+    match &args.command {
+        Some(command) => return run_command(&args, command, &config_path),
+        None if args.generate_config => return generate_config(&config_path),
+        None => {}
+    }
     // Load the mouse config file
-    let mouse_config = fs::read(args.config).map_err(Error::OpenConfig)?;
-    let mouse_config: LinkedHashMap<String, MouseProfile> = toml::from_slice(&mouse_config)?;
+    let mut mouse_config = load_profiles(&args, &config_path)?;
+    if args.check_config {
+        return check_config(&mouse_config);
+    }
+    check_models(&mouse_config)?;
+    // Only color output a person is looking at, see https://no-color.org
+    let color = args.output == OutputSink::Stdout
+        && io::stdout().is_terminal()
+        && env::var_os("NO_COLOR").is_none_or(|no_color| no_color.is_empty());
+    let new_formatter = |mouse_config: &Config| {
+        Formatter::new(
+            args.format,
+            mouse_config.thresholds,
+            args.format_template.clone(),
+            mouse_config.icons.clone(),
+            args.all_mice,
+            args.percent_unknown.clone(),
+            color,
+        )
+    };
+    let mut formatter = new_formatter(&mouse_config);
+    let mut output = Output::open(&args.output, args.output_append)
+        .map_err(|err| Error::OpenOutput(args.output.clone(), err))?;
+    // Only printed once, reloading the config rebuilds the formatter but continues the stream
+    if let Some(header) = formatter.header().filter(|_| !args.watch) {
+        output.line(&header).map_err(Error::WriteOutput)?;
+    }
+    let mut csv_log = args
+        .log_file
+        .as_deref()
+        .map(|path| CsvLog::open(path).map_err(|err| Error::OpenLogFile(path.to_owned(), err)))
+        .transpose()?;
+    let poll_options = PollOptions {
+        on_change: args.on_change,
+        read_retries: args.read_retries,
+        smooth: args.smooth,
+        charging_reads: args.charging_reads,
+        show_dpi: args.show_dpi,
+    };
+    // Initialize hidapi. Early in boot it may not be usable yet, so the daemon waits for it
+    let daemon = !(args.raw || args.oneshot || args.interval.is_zero());
+    let hid_api = if daemon {
+        init_hid_api(args.interval, args.hid_init_retries).await?
+    } else {
+        HidApi::new().map_err(Error::InitializeHidApi)?
+    };
+    let hid_api = Arc::new(Mutex::new(hid_api));
+    if args.raw {
+        return print_raw(&hid_api, &mouse_config, args.read_timeout_ms).await;
+    }
+    // Polling with no delay would spin, so an interval of 0 means print once
+    if args.oneshot || args.interval.is_zero() {
+        return run_oneshot(
+            &hid_api,
+            &mouse_config,
+            &args,
+            &poll_options,
+            &formatter,
+            &mut output,
+            csv_log.as_mut(),
+        )
+        .await;
+    }
     // Create a single sleep future
     // Initially we sleep for 0 (immediately get status)
     let sleep = time::sleep(Duration::from_secs(0));
-    let interval = Duration::from_secs(args.interval);
+    let interval = args.interval;
+    let mut animation = args
+        .animation_interval
+        .filter(|period| !period.is_zero())
+        .map(|period| {
+            let mut animation = time::interval_at(Instant::now() + period, period);
+            animation.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+            animation
+        });
+    let settle = Duration::from_millis(args.udev_settle_ms);
+    let udev_timeout = args.udev_timeout;
     tokio::pin!(sleep);
+    // Listen for requests to stop
+    let mut shutdown = Shutdown::new().map_err(Error::InstallSignalHandler)?;
+    // Reload the config when asked to
+    let mut sighup = signal(SignalKind::hangup()).map_err(Error::InstallSignalHandler)?;
+    let mut reload = false;
+    // Watch udev for our mice connecting and disconnecting
+    let mut monitor = if args.no_udev {
+        None
+    } else {
+        Some(udev_monitor()?)
+    };
+    let exports = start_exports(&args, &mouse_config).await?;
+    // Tell systemd we are ready, and ping its watchdog after each successful poll when asked to
+    // so a device wedged mid-read gets the service restarted
+    let mut watchdog_usec = 0;
+    let watchdog = sd_notify::watchdog_enabled(false, &mut watchdog_usec);
+    if let Err(err) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("Error notifying systemd: {err}");
+    }
+    // Low battery notification state for each profile, kept across reconnects
+    let mut notifiers: HashMap<String, LowBatteryNotifier> = HashMap::new();
+    // Successful reads left before exiting are counted down here, if limited
+    let mut schedule = Schedule {
+        interval,
+        jitter: args.jitter,
+        remaining: args.max_iterations,
+    };
+    // Show the readings saved by the last run until the mice are read
+    let mut state_file = None;
+    if args.persist_state {
+        match state_path() {
+            Some(path) => match StateFile::load(path.clone()) {
+                Ok(loaded) => state_file = Some(loaded),
+                Err(err) => error!("Error loading state file {}: {err}", path.display()),
+            },
+            None => warn!("No state directory, not persisting readings"),
+        }
+    }
+    let mut showing_saved = match &state_file {
+        Some(state_file) if !args.watch => show_saved(
+            state_file,
+            &mouse_config,
+            args.all_mice,
+            args.stale_after,
+            &formatter,
+            &mut output,
+        ),
+        _ => false,
+    };
+    let mut tui = if args.watch {
+        Some(Tui::open().map_err(Error::OpenTerminal)?)
+    } else {
+        None
+    };
+    // Device node each mouse was last opened on, by profile name
+    let mut nodes: HashMap<String, Node> = HashMap::new();
+    // Mice opened again on their old device nodes during the last udev wait
+    let mut reopened: Vec<(String, Box<dyn Mouse>, Node)> = Vec::new();
     // Main loop
-    loop {
-        // Initialize hidapi
-        let hid_api = HidApi::new().map_err(Error::InitializeHidApi)?;
-        // Look through the list of mice and try to find one
-        match open_first_mouse(&hid_api, mouse_config.iter()) {
-            Ok(mouse) => {
-                // Repeatedly send battery commands
-                loop {
-                    tokio::select! {
-                        () = &mut sleep => {
-                            // Get the battery status of the mouse
-                            match mouse.battery() {
-                                Ok(Some(battery_status)) => {
-                                    println!(
-                                        "\u{f8cc}{} {}%",
-                                        if battery_status.is_charging { "\u{f0e7}" } else {""},
-                                        battery_status.percent
-                                    )
-                                }
-                                Ok(None) => warn!("Error in response, will try again"),
-                                Err(err) => {
-                                    error!("Error reading battery status: {err}");
-                                    break;
-                                }
+    'main: loop {
+        // Swap in the new config now that no mouse borrows the old one
+        if std::mem::take(&mut reload) {
+            // Poll the reopened mice straight away
+            sleep.as_mut().reset(Instant::now());
+            let new_config = load_profiles(&args, &config_path)
+                .and_then(|new_config| check_models(&new_config).map(|()| new_config));
+            match new_config {
+                Ok(new_config) => {
+                    info!("Reloaded config from {}", config_path.display());
+                    mouse_config = new_config;
+                    formatter = new_formatter(&mouse_config);
+                    // Thresholds may have changed
+                    notifiers.clear();
+                    if let Some(mqtt) = &exports.mqtt {
+                        for (name, profile) in &mouse_config.profiles {
+                            mqtt.announce(name, &profile.model);
+                        }
+                    }
+                }
+                Err(err) => error!("Error reloading config, keeping the old one: {err}"),
+            }
+            // Pick up devices a new profile may match
+            if let Err(err) = lock_hid_api(&hid_api).refresh_devices() {
+                error!("Error refreshing HID device list: {err}");
+            }
+        }
+        // Look through the list of mice and try to open them, unless they were already opened
+        // again on the nodes they had before
+        let mut mice: Vec<_> = reopened
+            .drain(..)
+            .filter_map(|(name, mouse, node)| {
+                let (name, profile) = mouse_config.profiles.iter().find(|(n, _)| **n == name)?;
+                let mut open_mouse = OpenMouse::new(name, profile, mouse);
+                open_mouse.node = Some(node);
+                Some(open_mouse)
+            })
+            .collect();
+        if mice.is_empty() {
+            mice = open_mice(&hid_api, &mouse_config, &args).await;
+        }
+        // Repeatedly send battery commands until every mouse is gone
+        while !mice.is_empty() {
+            tokio::select! {
+                () = &mut sleep => {
+                    // The --watch display shows readings itself
+                    let printer = if tui.is_some() {
+                        None
+                    } else {
+                        Some((&formatter, &mut output))
+                    };
+                    let mut events = LoopEvents {
+                        hid_api: &hid_api,
+                        read_timeout: args.read_timeout_ms,
+                        exports: &exports,
+                        tui: &mut tui,
+                        watchdog,
+                        showing_saved: &mut showing_saved,
+                        state_file: &mut state_file,
+                        notifiers: args.notify.then_some(&mut notifiers),
+                        thresholds: mouse_config.thresholds,
+                        csv_log: &mut csv_log,
+                        nodes: &mut nodes,
+                    };
+                    let polled =
+                        poll_due(&mut mice, &mut schedule, printer, &poll_options, &mut events).await;
+                    if polled.is_break() {
+                        info!("Reached --max-iterations, shutting down");
+                        break 'main;
+                    }
+                    // Wait until the next mouse is due
+                    if let Some(next_poll) = mice.iter().map(|open_mouse| open_mouse.next_poll).min() {
+                        sleep.as_mut().reset(next_poll);
+                    }
+                },
+                Some(event) = next_udev_event(&mut monitor) => match event {
+                    // Stop polling a mouse as soon as it is unplugged, and start polling another
+                    // one as soon as it is plugged in
+                    Ok(event) => match process_udev_event(&event, mouse_config.profiles.iter()) {
+                        Ok(Some((name, DeviceChange::Connected)))
+                            if args.all_mice
+                                && !mice.iter().any(|open_mouse| open_mouse.name == name) =>
+                        {
+                            if let Some(open_mouse) =
+                                open_connected(&hid_api, &mouse_config, name, &args, settle).await
+                            {
+                                mice.push(open_mouse);
+                                sleep.as_mut().reset(Instant::now());
+                            }
+                        }
+                        Ok(Some((name, DeviceChange::Disconnected))) => {
+                            let node = mice
+                                .iter()
+                                .find(|open_mouse| open_mouse.name == name)
+                                .and_then(|open_mouse| open_mouse.node.clone());
+                            if let Some(node) = node {
+                                nodes.insert(name.to_owned(), node);
                             }
-                            // Wait for next interval
-                            sleep.as_mut().reset(Instant::now() + interval);
-                        },
+                            mice.retain(|open_mouse| open_mouse.name != name);
+                            exports.remove(name);
+                            match &mut tui {
+                                Some(tui) => tui.remove(name),
+                                None => remove_line(&formatter, &mut output, name),
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            error!("Unexpected error handling udev event: {err:?}");
+                        }
+                    },
+                    Err(err) => error!("Error processing udev event: {err}"),
+                },
+                _ = sighup.recv() => {
+                    info!("Received SIGHUP, reloading config");
+                    // The mice borrow the config, so let go of them and open them again
+                    for open_mouse in &mice {
+                        exports.remove(open_mouse.name);
                     }
+                    reload = true;
+                    continue 'main;
                 }
+                () = next_frame(&mut animation) => {
+                    for open_mouse in &mut mice {
+                        open_mouse.animate(&formatter, &mut output);
+                    }
+                }
+                () = shutdown.recv() => break 'main,
+                () = quit(&mut tui) => break 'main,
             }
-            Err(err) => {
-                error!("Error opening first mouse: {err}");
+        }
+        // Clear the status because we don't know the status of the mouse, unless the saved one
+        // is still shown
+        if let Some(line) = formatter
+            .disconnected()
+            .filter(|_| !showing_saved && !args.watch)
+        {
+            if let Err(err) = output.line(&line) {
+                error!("Error writing battery status: {err}");
             }
         }
-        // Print an empty line because we don't know the status of the mouse
-        println!();
-        // Do a udev wait loop until one of our desired mice show up
-        info!("Using udev to wait until our mouse appears");
-        let mut monitor: AsyncMonitorSocket = MonitorBuilder::new()
-            .map_err(Error::UdevBuildMonitor)?
-            .match_subsystem_devtype("usb", "usb_device")
-            .map_err(Error::UdevBuildMonitor)?
-            .listen()
-            .map_err(Error::UdevListen)?
-            .try_into()
-            .map_err(Error::UdevAsync)?;
+        // Do a udev wait loop until one of our desired mice show up. Without udev this just
+        // waits for the interval before looking again
+        let wait = if monitor.is_some() {
+            info!("Using udev to wait until our mouse appears");
+            udev_timeout
+        } else {
+            info!("Looking for our mouse again in {interval:?}");
+            interval
+        };
         // Set up the sleep timer to have a timeout before we stop checking udev
-        sleep.as_mut().reset(Instant::now() + interval);
+        sleep.as_mut().reset(Instant::now() + wait);
         // Process udev usb events
+        let mut connected = Vec::new();
         while let Some(event) = tokio::select! {
-            event = monitor.next() => { event },
+            event = next_udev_event(&mut monitor) => { event },
             _ = &mut sleep => { None },
+            _ = sighup.recv() => {
+                info!("Received SIGHUP, reloading config");
+                reload = true;
+                continue 'main;
+            }
+            () = shutdown.recv() => break 'main,
+            () = quit(&mut tui) => break 'main,
         } {
             match event {
-                Ok(event) => match process_udev_event(&event, mouse_config.iter()) {
-                    Ok(true) => break,
-                    Ok(false) => {}
+                Ok(event) => match process_udev_event(&event, mouse_config.profiles.iter()) {
+                    Ok(Some((name, DeviceChange::Connected))) => {
+                        connected.push(name);
+                        break;
+                    }
+                    Ok(_) => {}
                     Err(err) => {
                         error!("Unexpected error handling udev event: {err:?}");
                     }
@@ -209,23 +1569,263 @@ async fn main() -> Result<(), Error> {
                 Err(err) => error!("Error processing udev event: {err}"),
             }
         }
+        // Give the device time to finish enumerating, collapsing the burst of events it sends.
+        // With --all-mice other mice connecting meanwhile are noted too
+        if !connected.is_empty() {
+            sleep.as_mut().reset(Instant::now() + settle);
+            loop {
+                tokio::select! {
+                    Some(event) = next_udev_event(&mut monitor) => {
+                        if let Ok(event) = event {
+                            if let Ok(Some((name, DeviceChange::Connected))) =
+                                process_udev_event(&event, mouse_config.profiles.iter())
+                            {
+                                if args.all_mice && !connected.contains(&name) {
+                                    connected.push(name);
+                                }
+                            }
+                        }
+                    },
+                    () = &mut sleep => break,
+                    () = shutdown.recv() => break 'main,
+                    () = quit(&mut tui) => break 'main,
+                }
+            }
+        }
+        // Mice that came back on the device nodes they were last opened on, with the same serial
+        // numbers, can be opened without enumerating every device again. If any of them can't,
+        // the device list is refreshed for all of them
+        for name in connected {
+            let (Some(profile), Some(node)) = (mouse_config.profiles.get(name), nodes.get(name))
+            else {
+                reopened.clear();
+                break;
+            };
+            match open_node(&hid_api, name, profile, node, args.read_timeout_ms).await {
+                Ok(Some(mouse)) => reopened.push((name.clone(), mouse, node.clone())),
+                Ok(None) => {
+                    reopened.clear();
+                    break;
+                }
+                Err(err) => {
+                    error!("Error opening {name}: {err}");
+                    reopened.clear();
+                    break;
+                }
+            }
+        }
+        if !reopened.is_empty() {
+            debug!("Mice are back on their previous device nodes, keeping the device list");
+        } else if let Err(err) = lock_hid_api(&hid_api).refresh_devices() {
+            // Rescan the HID bus so newly connected mice show up
+            error!("Error refreshing HID device list: {err}");
+        }
+    }
+    // Clear the status since we are no longer tracking the mouse
+    drop(tui);
+    if let Some(line) = formatter.disconnected().filter(|_| !args.watch) {
+        output.line(&line).map_err(Error::WriteOutput)?;
     }
+    if let Some(path) = &args.socket {
+        if let Err(err) = fs::remove_file(path) {
+            warn!("Error removing status socket: {err}");
+        }
+    }
+    if let Some(mqtt) = exports.mqtt {
+        mqtt.shutdown().await;
+    }
+    Ok(())
 }
 
 #[derive(Debug, thiserror::Error)]
 enum Error {
     #[error("Error setting tracing subscriber: {0}")]
     SetTracingSubscriber(#[from] tracing::subscriber::SetGlobalDefaultError),
+    #[error(
+        "No config file at {}, create one with `nezumi --config {0:?} --generate-config`",
+        .0.display()
+    )]
+    ConfigNotFound(PathBuf),
     #[error("Error opening config file: {0}")]
     OpenConfig(io::Error),
+    #[error("Config file {} already exists", .0.display())]
+    ConfigExists(PathBuf),
+    #[error("Error writing config file: {0}")]
+    WriteConfig(io::Error),
+    #[error("Config file is not valid UTF-8: {0}")]
+    ConfigNotUtf8(std::string::FromUtf8Error),
     #[error("Error parsing config file: {0}")]
     ParseConfig(#[from] toml::de::Error),
+    #[error(
+        "Config file {} has no mouse profiles, add a table for at least one mouse, see \
+         `nezumi --generate-config` for an example",
+        .0.display()
+    )]
+    NoProfiles(PathBuf),
+    #[error("Error installing signal handler: {0}")]
+    InstallSignalHandler(io::Error),
+    #[error(
+        "Profiles with unknown models: {0}, run `nezumi --generate-config` to list the supported \
+         models"
+    )]
+    UnknownModels(String),
+    #[error("Error opening log file {}: {1}", .0.display())]
+    OpenLogFile(PathBuf, io::Error),
+    #[error("Error writing to log file: {0}")]
+    WriteLogFile(io::Error),
+    #[error("Error binding status socket {}: {1}", .0.display())]
+    BindSocket(PathBuf, io::Error),
+    #[error("Error serving metrics on {0}: {1}")]
+    BindMetrics(SocketAddr, metrics::BindError),
+    #[error("Error starting D-Bus service: {0}")]
+    StartDbus(zbus::Error),
     #[error("Error initializing hidapi: {0}")]
     InitializeHidApi(hidapi::HidError),
+    #[error("Error opening mouse: {0}")]
+    OpenMouse(#[from] OpenFirstMouseError),
+    #[error("Error reading battery status: {0}")]
+    ReadBattery(hidapi::HidError),
+    #[error("No HID device with that id and interface found")]
+    DeviceNotFound,
+    #[error("Error probing device: {0}")]
+    Probe(hidapi::HidError),
+    #[error("Error reading firmware version: {0}")]
+    ReadFirmware(hidapi::HidError),
+    #[error("Mouse did not report a battery status")]
+    NoBatteryStatus,
     #[error("Error building udev monitor builder: {0}")]
     UdevBuildMonitor(io::Error),
     #[error("Error listening to udev: {0}")]
     UdevListen(io::Error),
     #[error("Error creating async udev socket: {0}")]
     UdevAsync(io::Error),
+    #[error("Error opening output {0}: {1}")]
+    OpenOutput(OutputSink, io::Error),
+    #[error("Error writing output: {0}")]
+    WriteOutput(io::Error),
+    #[error("Error setting up the terminal for --watch: {0}")]
+    OpenTerminal(io::Error),
+}
+impl Error {
+    /// Exit code for the error, following sysexits.h so scripts can tell why nezumi failed
+    fn exit_code(&self) -> ExitCode {
+        /// No mouse is connected
+        const EX_UNAVAILABLE: u8 = 69;
+        /// A mouse was found but no driver could be made for it
+        const EX_SOFTWARE: u8 = 70;
+        /// A mouse was found but we may not open it
+        const EX_NOPERM: u8 = 77;
+        /// The config is missing or invalid
+        const EX_CONFIG: u8 = 78;
+        match self {
+            Error::ConfigNotFound(_)
+            | Error::OpenConfig(_)
+            | Error::ConfigNotUtf8(_)
+            | Error::ParseConfig(_)
+            | Error::NoProfiles(_)
+            | Error::UnknownModels(_) => ExitCode::from(EX_CONFIG),
+            Error::OpenMouse(OpenFirstMouseError::NotFound) | Error::DeviceNotFound => {
+                ExitCode::from(EX_UNAVAILABLE)
+            }
+            Error::OpenMouse(OpenFirstMouseError::WrapMouse(_)) => ExitCode::from(EX_SOFTWARE),
+            Error::OpenMouse(OpenFirstMouseError::PermissionDenied(_)) => ExitCode::from(EX_NOPERM),
+            _ => ExitCode::FAILURE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    /// Captures everything written to an [`Output`]
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn saved_readings_are_stale() {
+        let mouse_config: Config = toml::from_str(
+            "[wireless]
+model = \"steelseries_aerox_9_wireless\"
+\
+             vendor = \"1038\"\nproduct = \"1858\"\nendpoint = 3",
+        )
+        .unwrap();
+        let path = env::temp_dir().join(format!("nezumi-state-{}.json", std::process::id()));
+        let mut state_file = StateFile::load(path.clone()).unwrap();
+        state_file
+            .record(
+                "wireless",
+                &mouse::BatteryStatus {
+                    is_charging: false,
+                    percent: 45,
+                },
+            )
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        let show = |state_file: &StateFile, format, stale_after| {
+            let formatter = Formatter::new(
+                format,
+                Thresholds::default(),
+                Template::default(),
+                Icons::default(),
+                false,
+                None,
+                false,
+            );
+            let captured = Captured::default();
+            let mut output = Output::Stream(io::BufWriter::new(Box::new(captured.clone())));
+            let shown = show_saved(
+                state_file,
+                &mouse_config,
+                false,
+                stale_after,
+                &formatter,
+                &mut output,
+            );
+            let line = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+            (shown, line)
+        };
+        for format in Format::value_variants() {
+            let (shown, line) = show(&state_file, *format, None);
+            assert!(shown);
+            assert!(line.contains("stale"), "{format:?}: {line}");
+            assert!(!line.contains("last known level"), "{format:?}: {line}");
+        }
+        let (_, line) = show(&state_file, Format::Percent, None);
+        assert_eq!(line, "45 (stale)\n");
+        let (_, line) = show(&state_file, Format::Json, None);
+        assert!(line.contains("\"stale\":true"));
+        assert!(!line.contains("connection"));
+        // Still fresh enough to show
+        assert!(show(&state_file, Format::Percent, Some(Duration::from_secs(60))).0);
+        // Saved too long ago
+        fs::write(
+            &path,
+            r#"{"wireless":{"percent":45,"charging":false,"timestamp":0}}"#,
+        )
+        .unwrap();
+        let state_file = StateFile::load(path.clone()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!show(&state_file, Format::Percent, Some(Duration::from_secs(60))).0);
+    }
+
+    #[test]
+    fn udev_ids() {
+        assert_eq!(parse_udev_id("046d"), Some(0x046d));
+        assert_eq!(parse_udev_id("46d"), Some(0x046d));
+        assert_eq!(parse_udev_id("046d\n"), Some(0x046d));
+        assert_eq!(parse_udev_id(""), None);
+        assert_eq!(parse_udev_id("1046d"), None);
+        assert_eq!(parse_udev_id("xyz"), None);
+    }
 }
@@ -0,0 +1,246 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use nezumi::mouse::BatteryStatus;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tiny_http::{Header, Response, Server};
+use tracing::debug;
+
+/// Error starting the metrics server
+pub type BindError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Battery metrics exported to Prometheus
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Model and latest battery status of each connected mouse, keyed by profile name
+    batteries: BTreeMap<String, (String, BatteryStatus)>,
+    /// Number of battery reads that failed with a HID error
+    read_errors: u64,
+    /// Number of battery reads the mouse gave no usable response to
+    no_responses: u64,
+    /// Model and durations of the battery reads of each mouse seen, keyed by profile name
+    read_durations: BTreeMap<String, (String, Histogram)>,
+}
+
+/// Upper bounds of the read duration histogram buckets, in seconds
+const READ_DURATION_BUCKETS: [f64; 9] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+/// Cumulative histogram of read durations
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Number of observations at or below each bucket's bound
+    buckets: [u64; READ_DURATION_BUCKETS.len()],
+    /// Sum of all observations, in seconds
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(READ_DURATION_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+impl Metrics {
+    /// Records the latest battery status of a mouse
+    pub fn set(&mut self, name: &str, model: &str, status: BatteryStatus) {
+        self.batteries
+            .insert(name.to_owned(), (model.to_owned(), status));
+    }
+
+    /// Stops exporting the battery status of a mouse that is gone
+    pub fn remove(&mut self, name: &str) {
+        self.batteries.remove(name);
+    }
+
+    /// Counts a battery read that failed with a HID error
+    pub fn read_error(&mut self) {
+        self.read_errors += 1;
+    }
+
+    /// Counts a battery read that got no usable response
+    pub fn no_response(&mut self) {
+        self.no_responses += 1;
+    }
+
+    /// Records how long a battery read took
+    pub fn read_duration(&mut self, name: &str, model: &str, duration: Duration) {
+        self.read_durations
+            .entry(name.to_owned())
+            .or_insert_with(|| (model.to_owned(), Histogram::default()))
+            .1
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String never fails
+        let _ = writeln!(
+            out,
+            "# HELP nezumi_battery_percent Battery charge in percent"
+        );
+        let _ = writeln!(out, "# TYPE nezumi_battery_percent gauge");
+        for (name, (model, status)) in &self.batteries {
+            let _ = writeln!(
+                out,
+                "nezumi_battery_percent{} {}",
+                labels(name, model),
+                status.percent
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP nezumi_battery_charging Whether the mouse is charging"
+        );
+        let _ = writeln!(out, "# TYPE nezumi_battery_charging gauge");
+        for (name, (model, status)) in &self.batteries {
+            let _ = writeln!(
+                out,
+                "nezumi_battery_charging{} {}",
+                labels(name, model),
+                u8::from(status.is_charging)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP nezumi_read_errors_total Battery reads that failed with a HID error"
+        );
+        let _ = writeln!(out, "# TYPE nezumi_read_errors_total counter");
+        let _ = writeln!(out, "nezumi_read_errors_total {}", self.read_errors);
+        let _ = writeln!(
+            out,
+            "# HELP nezumi_no_response_total Battery reads that got no usable response"
+        );
+        let _ = writeln!(out, "# TYPE nezumi_no_response_total counter");
+        let _ = writeln!(out, "nezumi_no_response_total {}", self.no_responses);
+        let _ = writeln!(
+            out,
+            "# HELP nezumi_read_duration_seconds Time taken by each battery read"
+        );
+        let _ = writeln!(out, "# TYPE nezumi_read_duration_seconds histogram");
+        for (name, (model, histogram)) in &self.read_durations {
+            let labels = format!("name=\"{}\",model=\"{}\"", escape(name), escape(model));
+            for (bucket, bound) in histogram.buckets.iter().zip(READ_DURATION_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "nezumi_read_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {bucket}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "nezumi_read_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "nezumi_read_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "nezumi_read_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            );
+        }
+        out
+    }
+}
+
+/// Formats the label set identifying a mouse
+fn labels(name: &str, model: &str) -> String {
+    format!("{{name=\"{}\",model=\"{}\"}}", escape(name), escape(model))
+}
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves the metrics over HTTP on a background thread
+pub fn serve(addr: SocketAddr, metrics: Arc<Mutex<Metrics>>) -> Result<(), BindError> {
+    let server = Server::http(addr)?;
+    let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+        .expect("content type header is valid");
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let body = metrics
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .render();
+            let response = Response::from_string(body).with_header(content_type.clone());
+            if let Err(err) = request.respond(response) {
+                debug!("Error responding to metrics request: {err}");
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let mut metrics = Metrics::default();
+        metrics.set(
+            "aerox",
+            "steelseries_aerox_9_wireless",
+            BatteryStatus {
+                is_charging: true,
+                percent: 42,
+            },
+        );
+        metrics.read_error();
+        metrics.no_response();
+        metrics.no_response();
+        metrics.read_duration(
+            "aerox",
+            "steelseries_aerox_9_wireless",
+            Duration::from_millis(20),
+        );
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "nezumi_battery_percent{name=\"aerox\",model=\"steelseries_aerox_9_wireless\"} 42\n"
+        ));
+        assert!(rendered.contains(
+            "nezumi_battery_charging{name=\"aerox\",model=\"steelseries_aerox_9_wireless\"} 1\n"
+        ));
+        assert!(rendered.contains("nezumi_read_errors_total 1\n"));
+        assert!(rendered.contains("nezumi_no_response_total 2\n"));
+        assert!(rendered.contains(
+            "nezumi_read_duration_seconds_bucket{name=\"aerox\",\
+             model=\"steelseries_aerox_9_wireless\",le=\"0.01\"} 0\n"
+        ));
+        assert!(rendered.contains(
+            "nezumi_read_duration_seconds_bucket{name=\"aerox\",\
+             model=\"steelseries_aerox_9_wireless\",le=\"0.025\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "nezumi_read_duration_seconds_count{name=\"aerox\",\
+             model=\"steelseries_aerox_9_wireless\"} 1\n"
+        ));
+        metrics.remove("aerox");
+        assert!(!metrics.render().contains("nezumi_battery_percent{"));
+    }
+}
@@ -9,83 +9,1280 @@
 // You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
 use hidapi::{HidDevice, HidError};
 
-pub fn get_mouse(model: &str, device: HidDevice) -> Result<Box<dyn Mouse>, GetMouseError> {
-    match model {
-        "steelseries_aerox_9_wired" => Ok(Box::new(aerox9::Wired::new(device))),
-        "steelseries_aerox_9_wireless" => Ok(Box::new(aerox9::Wireless::new(device))),
-        other => Err(GetMouseError(other.into())),
-    }
+pub mod corsair;
+pub mod glorious;
+pub mod logitech;
+pub mod razer;
+pub mod steelseries;
+
+/// Settings of a profile passed on to its driver
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// How long to wait for each response, in milliseconds
+    pub read_timeout: i32,
+    /// Read the battery from a feature report when the mouse doesn't answer the interrupt read,
+    /// only used by the Aerox 9 Wireless
+    pub feature_report_fallback: bool,
+    /// Offset of the battery byte in a response, overriding the model's, only used by the
+    /// Aerox 9
+    pub response_offset: Option<usize>,
+    /// Report id written before each request, or `None` to write the request bare, only used
+    /// by the Aerox mice and the generic SteelSeries driver
+    pub report_id: Option<u8>,
+    /// Bit of the battery byte set while charging, overriding the model's, only used by the
+    /// Aerox 9
+    pub charging_flag: Option<u8>,
+    /// Bit of the request asking the receiver, overriding the model's, only used by the Aerox 9
+    /// Wireless
+    pub wireless_flag: Option<u8>,
+}
+
+/// Report id the Aerox mice expect before each request
+pub const DEFAULT_REPORT_ID: Option<u8> = Some(0x00);
+
+/// Prefixes a request with its report id, if any
+fn with_report_id(report_id: Option<u8>, request: &[u8]) -> Vec<u8> {
+    report_id
+        .into_iter()
+        .chain(request.iter().copied())
+        .collect()
+}
+
+/// Builds the driver for a model from its device and options
+type Constructor = fn(HidDevice, &Options) -> Box<dyn Mouse>;
+
+/// Every model accepted by [`get_mouse`], along with how to build its driver
+const MODELS: &[(&str, Constructor)] = &[
+    ("steelseries_aerox_9_wired", |device, options| {
+        Box::new(
+            aerox9::Wired::new(device, options.read_timeout)
+                .with_response_offset(options.response_offset.unwrap_or(aerox9::OFFSET_BATTERY))
+                .with_charging_flag(options.charging_flag.unwrap_or(aerox9::FLAG_CHARGING))
+                .with_report_id(options.report_id),
+        )
+    }),
+    ("steelseries_aerox_9_wireless", |device, options| {
+        Box::new(
+            aerox9::Wireless::new(device, options.read_timeout)
+                .with_response_offset(options.response_offset.unwrap_or(aerox9::OFFSET_BATTERY))
+                .with_charging_flag(options.charging_flag.unwrap_or(aerox9::FLAG_CHARGING))
+                .with_wireless_flag(options.wireless_flag.unwrap_or(aerox9::FLAG_WIRELESS))
+                .with_feature_report_fallback(options.feature_report_fallback)
+                .with_report_id(options.report_id),
+        )
+    }),
+    ("steelseries_aerox_3_wired", |device, options| {
+        Box::new(aerox3::Wired::new(device, options.read_timeout).with_report_id(options.report_id))
+    }),
+    ("steelseries_aerox_3_wireless", |device, options| {
+        Box::new(
+            aerox3::Wireless::new(device, options.read_timeout).with_report_id(options.report_id),
+        )
+    }),
+    ("steelseries_aerox_5_wired", |device, options| {
+        Box::new(aerox5::Wired::new(device, options.read_timeout).with_report_id(options.report_id))
+    }),
+    ("steelseries_aerox_5_wireless", |device, options| {
+        Box::new(
+            aerox5::Wireless::new(device, options.read_timeout).with_report_id(options.report_id),
+        )
+    }),
+    ("steelseries_prime_wireless", |device, options| {
+        Box::new(prime::Wireless::new(device, options.read_timeout))
+    }),
+    ("steelseries_rival_3_wireless", |device, options| {
+        Box::new(rival3::Wireless::new(device, options.read_timeout))
+    }),
+    ("steelseries_generic_wireless", |device, options| {
+        Box::new(
+            steelseries_generic::Wireless::new(device, options.read_timeout)
+                .with_report_id(options.report_id),
+        )
+    }),
+    ("corsair_dark_core_rgb_pro", |device, options| {
+        Box::new(corsair::Wireless::new(device, options.read_timeout))
+    }),
+    ("glorious_model_o_wireless", |device, options| {
+        Box::new(glorious::Wireless::new(device, options.read_timeout))
+    }),
+    ("logitech_g_pro_wireless", |device, options| {
+        Box::new(logitech::Wireless::new(device, options.read_timeout))
+    }),
+    ("razer_deathadder_v2_pro", |device, options| {
+        Box::new(razer::Wireless::new(
+            device,
+            options.read_timeout,
+            &razer::DEATHADDER_V2_PRO,
+        ))
+    }),
+    ("razer_viper_ultimate", |device, options| {
+        Box::new(razer::Wireless::new(
+            device,
+            options.read_timeout,
+            &razer::VIPER_ULTIMATE,
+        ))
+    }),
+];
+
+/// Model names accepted by [`get_mouse`]
+pub fn supported_models() -> impl Iterator<Item = &'static str> {
+    MODELS.iter().map(|(model, _)| *model)
+}
+
+pub fn get_mouse(
+    model: &str,
+    device: HidDevice,
+    options: &Options,
+) -> Result<Box<dyn Mouse>, GetMouseError> {
+    let (_, constructor) = MODELS
+        .iter()
+        .find(|(name, _)| *name == model)
+        .ok_or_else(|| GetMouseError(model.into()))?;
+    Ok(constructor(device, options))
 }
 
 #[derive(Debug, thiserror::Error)]
-#[error("Invalid model: {0}")]
+#[error(
+    "Invalid model: {0}, supported models are {}",
+    supported_models().collect::<Vec<_>>().join(", ")
+)]
 pub struct GetMouseError(String);
 
 pub mod aerox9 {
-    use super::{BatteryStatus, HidDevice, HidError, Mouse};
+    use super::steelseries::{decode_battery, DecodeConfig, AEROX_9};
+    use super::{
+        with_report_id, Connection, HidDevice, HidError, HidTransport, Mouse, MouseState,
+        RawBattery, DEFAULT_REPORT_ID,
+    };
+    use std::cell::Cell;
 
     const OP_BATTERY_REQUEST: u8 = 0x92;
-    const OP_BATTERY_RESPONSE_LEN: usize = 2;
-    const FLAG_BATTERY_CHARGING: u8 = 0b10000000;
+    /// Bit of the request asking the receiver rather than a cable
+    pub const FLAG_WIRELESS: u8 = 0b01000000;
+    /// Bit of the battery byte set while charging
+    pub const FLAG_CHARGING: u8 = AEROX_9.charging;
+    /// Offset of the battery byte in a response
+    pub const OFFSET_BATTERY: usize = 1;
+
+    /// Sends a battery request and decodes the battery byte at `offset` of the response, with
+    /// `charging_flag` set while charging
+    ///
+    /// With `feature_report_fallback`, a response that doesn't arrive as an interrupt report is
+    /// read as a feature report instead, which some firmware revisions answer with
+    fn battery_raw<T: HidTransport>(
+        device: &T,
+        report_id: Option<u8>,
+        request: u8,
+        read_timeout: i32,
+        offset: usize,
+        charging_flag: u8,
+        feature_report_fallback: bool,
+    ) -> Result<RawBattery, HidError> {
+        let response_len = offset + 1;
+        // First, write the request
+        device.write(&with_report_id(report_id, &[request]))?;
+        // Then, read a response
+        let mut response = vec![0; response_len];
+        let len = device.read_timeout(&mut response, read_timeout)?;
+        response.truncate(len);
+        if len < response_len && feature_report_fallback {
+            // Feature reports start with the report id
+            let mut report = vec![0; response_len + 1];
+            report[0] = report_id.unwrap_or(0);
+            let len = device.get_feature_report(&mut report)?;
+            response = report[1..len.max(1)].to_vec();
+        }
+        // Nothing or only part of a response arrived before the timeout
+        if response.len() < response_len {
+            return Ok(RawBattery {
+                response,
+                status: MouseState::NoResponse,
+            });
+        }
+        // Extract fields
+        Ok(RawBattery {
+            status: decode_battery(
+                response[offset],
+                DecodeConfig {
+                    charging: charging_flag,
+                    ..AEROX_9
+                },
+            ),
+            response,
+        })
+    }
+
+    pub struct Wired<T = HidDevice> {
+        device: T,
+        read_timeout: i32,
+        /// Offset of the battery byte in a response
+        response_offset: usize,
+        /// Bit of the battery byte set while charging
+        charging_flag: u8,
+        /// Written before each request
+        report_id: Option<u8>,
+    }
+    impl<T: HidTransport> Wired<T> {
+        /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+        pub fn new(device: T, read_timeout: i32) -> Self {
+            Wired {
+                device,
+                read_timeout,
+                response_offset: OFFSET_BATTERY,
+                charging_flag: FLAG_CHARGING,
+                report_id: DEFAULT_REPORT_ID,
+            }
+        }
+
+        /// Reads the battery from another byte of the response than [`OFFSET_BATTERY`]
+        pub fn with_response_offset(self, response_offset: usize) -> Self {
+            Wired {
+                response_offset,
+                ..self
+            }
+        }
+
+        /// Reads the charging state from another bit of the battery byte than
+        /// [`FLAG_CHARGING`]
+        pub fn with_charging_flag(self, charging_flag: u8) -> Self {
+            Wired {
+                charging_flag,
+                ..self
+            }
+        }
+
+        /// Writes requests with another report id than [`DEFAULT_REPORT_ID`], or none
+        pub fn with_report_id(self, report_id: Option<u8>) -> Self {
+            Wired { report_id, ..self }
+        }
+    }
+    impl<T: HidTransport> Mouse for Wired<T> {
+        fn battery(&self) -> Result<MouseState, HidError> {
+            Ok(self.battery_raw()?.status)
+        }
+
+        fn battery_raw(&self) -> Result<RawBattery, HidError> {
+            battery_raw(
+                &self.device,
+                self.report_id,
+                OP_BATTERY_REQUEST,
+                self.read_timeout,
+                self.response_offset,
+                self.charging_flag,
+                false,
+            )
+        }
+    }
+    pub struct Wireless<T = HidDevice> {
+        device: T,
+        read_timeout: i32,
+        /// Link state of the last response
+        connection: Cell<Option<Connection>>,
+        /// Offset of the battery byte in a response
+        response_offset: usize,
+        /// Bit of the battery byte set while charging
+        charging_flag: u8,
+        /// Bit of the request asking the receiver
+        wireless_flag: u8,
+        /// Fall back to a feature report when the interrupt read times out
+        feature_report_fallback: bool,
+        /// Written before each request
+        report_id: Option<u8>,
+    }
+    impl<T: HidTransport> Wireless<T> {
+        /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+        pub fn new(device: T, read_timeout: i32) -> Self {
+            Wireless {
+                device,
+                read_timeout,
+                connection: Cell::new(None),
+                response_offset: OFFSET_BATTERY,
+                charging_flag: FLAG_CHARGING,
+                wireless_flag: FLAG_WIRELESS,
+                feature_report_fallback: false,
+                report_id: DEFAULT_REPORT_ID,
+            }
+        }
+
+        /// Reads the battery from another byte of the response than [`OFFSET_BATTERY`]
+        pub fn with_response_offset(self, response_offset: usize) -> Self {
+            Wireless {
+                response_offset,
+                ..self
+            }
+        }
+
+        /// Reads the charging state from another bit of the battery byte than
+        /// [`FLAG_CHARGING`]
+        pub fn with_charging_flag(self, charging_flag: u8) -> Self {
+            Wireless {
+                charging_flag,
+                ..self
+            }
+        }
+
+        /// Marks requests for the receiver with another bit than [`FLAG_WIRELESS`]
+        pub fn with_wireless_flag(self, wireless_flag: u8) -> Self {
+            Wireless {
+                wireless_flag,
+                ..self
+            }
+        }
+
+        /// Reads the battery from a feature report when the interrupt read times out
+        pub fn with_feature_report_fallback(self, feature_report_fallback: bool) -> Self {
+            Wireless {
+                feature_report_fallback,
+                ..self
+            }
+        }
+
+        /// Writes requests with another report id than [`DEFAULT_REPORT_ID`], or none
+        pub fn with_report_id(self, report_id: Option<u8>) -> Self {
+            Wireless { report_id, ..self }
+        }
+    }
+    impl<T: HidTransport> Mouse for Wireless<T> {
+        fn battery(&self) -> Result<MouseState, HidError> {
+            Ok(self.battery_raw()?.status)
+        }
+
+        fn battery_raw(&self) -> Result<RawBattery, HidError> {
+            let request = OP_BATTERY_REQUEST | self.wireless_flag;
+            let raw = battery_raw(
+                &self.device,
+                self.report_id,
+                request,
+                self.read_timeout,
+                self.response_offset,
+                self.charging_flag,
+                self.feature_report_fallback,
+            )?;
+            self.connection.set(connection_from_response(&raw, request));
+            Ok(raw)
+        }
+
+        fn connection(&self) -> Option<Connection> {
+            self.connection.get()
+        }
+    }
+
+    /// Decodes the link state from the first byte of a response
+    ///
+    /// The mouse echoes the request opcode, anything else was filled in by the receiver
+    pub fn connection_from_response(raw: &RawBattery, request: u8) -> Option<Connection> {
+        let MouseState::Battery(_) = raw.status else {
+            return None;
+        };
+        Some(if raw.response[0] == request {
+            Connection::Connected
+        } else {
+            Connection::Stale
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mouse::mock::MockTransport;
+        use crate::mouse::BatteryStatus;
+
+        #[test]
+        fn wired_request() {
+            let mouse = Wired::new(MockTransport::new([vec![0x00, 0x0a]]), 200);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: false,
+                    percent: 45
+                })
+            );
+            assert_eq!(mouse.device.written(), [vec![0x00, 0x92]]);
+        }
+
+        #[test]
+        fn wireless_request() {
+            let mouse = Wireless::new(MockTransport::new([vec![0x00, 0x8a]]), 200);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: true,
+                    percent: 45
+                })
+            );
+            assert_eq!(mouse.device.written(), [vec![0x00, 0xd2]]);
+        }
+
+        #[test]
+        fn connection() {
+            let mouse = Wireless::new(
+                MockTransport::new([vec![0xd2, 0x0a], vec![0x00, 0x0a], vec![]]),
+                200,
+            );
+            mouse.battery().unwrap();
+            assert_eq!(mouse.connection(), Some(Connection::Connected));
+            mouse.battery().unwrap();
+            assert_eq!(mouse.connection(), Some(Connection::Stale));
+            mouse.battery().unwrap();
+            assert_eq!(mouse.connection(), None);
+        }
+
+        #[test]
+        fn feature_report_fallback() {
+            let mouse = Wireless::new(MockTransport::new([vec![], vec![0x00, 0xd2, 0x0a]]), 200)
+                .with_feature_report_fallback(true);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: false,
+                    percent: 45
+                })
+            );
+            let mouse = Wireless::new(MockTransport::new([vec![], vec![0x00]]), 200)
+                .with_feature_report_fallback(true);
+            assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+        }
+
+        #[test]
+        fn response_offset() {
+            let mouse = Wired::new(MockTransport::new([vec![0x00, 0x00, 0x0a]]), 200)
+                .with_response_offset(2);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: false,
+                    percent: 45
+                })
+            );
+            let mouse =
+                Wired::new(MockTransport::new([vec![0x00, 0x0a]]), 200).with_response_offset(2);
+            assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+        }
+
+        #[test]
+        fn report_id() {
+            let mouse = Wired::new(MockTransport::new([vec![0x00, 0x0a]]), 200);
+            mouse.battery().unwrap();
+            assert_eq!(mouse.device.written(), [vec![0x00, 0x92]]);
+            let mouse =
+                Wired::new(MockTransport::new([vec![0x00, 0x0a]]), 200).with_report_id(Some(0x02));
+            mouse.battery().unwrap();
+            assert_eq!(mouse.device.written(), [vec![0x02, 0x92]]);
+            let mouse =
+                Wired::new(MockTransport::new([vec![0x00, 0x0a]]), 200).with_report_id(None);
+            mouse.battery().unwrap();
+            assert_eq!(mouse.device.written(), [vec![0x92]]);
+        }
+
+        #[test]
+        fn flags() {
+            let mouse = Wireless::new(MockTransport::new([vec![0x00, 0x4a]]), 200)
+                .with_charging_flag(0b01000000)
+                .with_wireless_flag(0b00100000);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: true,
+                    percent: 45
+                })
+            );
+            assert_eq!(mouse.device.written(), [vec![0x00, 0xb2]]);
+        }
+
+        #[test]
+        fn timeout_is_no_response() {
+            let mouse = Wireless::new(MockTransport::new([vec![]]), 200);
+            assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+        }
+
+        #[test]
+        fn raw_response() {
+            let mouse = Wired::new(MockTransport::new([vec![0x00, 0x8a], vec![0x00]]), 200);
+            assert_eq!(
+                mouse.battery_raw().unwrap(),
+                RawBattery {
+                    response: vec![0x00, 0x8a],
+                    status: MouseState::Battery(BatteryStatus {
+                        is_charging: true,
+                        percent: 45
+                    })
+                }
+            );
+            assert_eq!(
+                mouse.battery_raw().unwrap(),
+                RawBattery {
+                    response: vec![0x00],
+                    status: MouseState::NoResponse
+                }
+            );
+        }
+
+        #[test]
+        fn short_read_is_no_response() {
+            let mouse = Wired::new(MockTransport::new([vec![0x00]]), 200);
+            assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+        }
+    }
+}
+
+pub mod aerox3 {
+    use super::steelseries::{decode_battery, AEROX_9};
+    use super::{
+        with_report_id, HidDevice, HidError, HidTransport, Mouse, MouseState, RawBattery,
+        DEFAULT_REPORT_ID,
+    };
+
+    /// The Aerox 3 uses the same opcode whether or not it is wireless
+    const OP_BATTERY_REQUEST: u8 = 0xd2;
+    /// Report id, battery byte, then a byte we don't use
+    const OP_BATTERY_RESPONSE_LEN: usize = 3;
+    /// Offset of the battery byte in a response
+    const OFFSET_BATTERY: usize = 1;
+
+    /// Requests the battery status and decodes it like the Aerox 9 does
+    fn battery_raw<T: HidTransport>(
+        device: &T,
+        report_id: Option<u8>,
+        read_timeout: i32,
+    ) -> Result<RawBattery, HidError> {
+        // First, write the request
+        device.write(&with_report_id(report_id, &[OP_BATTERY_REQUEST]))?;
+        // Then, read a response
+        let mut response = [0; OP_BATTERY_RESPONSE_LEN];
+        let len = device.read_timeout(&mut response, read_timeout)?;
+        // Nothing arrived before the timeout, or it stopped short of the battery byte
+        let status = if len <= OFFSET_BATTERY {
+            MouseState::NoResponse
+        } else {
+            // Extract fields
+            decode_battery(response[OFFSET_BATTERY], AEROX_9)
+        };
+        Ok(RawBattery {
+            response: response[..len].to_vec(),
+            status,
+        })
+    }
+
+    pub struct Wired<T = HidDevice> {
+        device: T,
+        read_timeout: i32,
+        /// Written before each request
+        report_id: Option<u8>,
+    }
+    impl<T: HidTransport> Wired<T> {
+        /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+        pub fn new(device: T, read_timeout: i32) -> Self {
+            Wired {
+                device,
+                read_timeout,
+                report_id: DEFAULT_REPORT_ID,
+            }
+        }
+
+        /// Writes requests with another report id than [`DEFAULT_REPORT_ID`], or none
+        pub fn with_report_id(self, report_id: Option<u8>) -> Self {
+            Wired { report_id, ..self }
+        }
+    }
+    impl<T: HidTransport> Mouse for Wired<T> {
+        fn battery(&self) -> Result<MouseState, HidError> {
+            Ok(self.battery_raw()?.status)
+        }
+
+        fn battery_raw(&self) -> Result<RawBattery, HidError> {
+            battery_raw(&self.device, self.report_id, self.read_timeout)
+        }
+    }
+    pub struct Wireless<T = HidDevice> {
+        device: T,
+        read_timeout: i32,
+        /// Written before each request
+        report_id: Option<u8>,
+    }
+    impl<T: HidTransport> Wireless<T> {
+        /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+        pub fn new(device: T, read_timeout: i32) -> Self {
+            Wireless {
+                device,
+                read_timeout,
+                report_id: DEFAULT_REPORT_ID,
+            }
+        }
+
+        /// Writes requests with another report id than [`DEFAULT_REPORT_ID`], or none
+        pub fn with_report_id(self, report_id: Option<u8>) -> Self {
+            Wireless { report_id, ..self }
+        }
+    }
+    impl<T: HidTransport> Mouse for Wireless<T> {
+        fn battery(&self) -> Result<MouseState, HidError> {
+            Ok(self.battery_raw()?.status)
+        }
+
+        fn battery_raw(&self) -> Result<RawBattery, HidError> {
+            battery_raw(&self.device, self.report_id, self.read_timeout)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mouse::mock::MockTransport;
+        use crate::mouse::BatteryStatus;
+
+        #[test]
+        fn wireless_request() {
+            let mouse = Wireless::new(MockTransport::new([vec![0x00, 0x0a, 0x00]]), 200);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: false,
+                    percent: 45
+                })
+            );
+            assert_eq!(mouse.device.written(), [vec![0x00, 0xd2]]);
+        }
+
+        #[test]
+        fn short_read_is_no_response() {
+            for response in [vec![], vec![0x00]] {
+                let mouse = Wired::new(MockTransport::new([response]), 200);
+                assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+            }
+            // The last byte isn't needed
+            let mouse = Wired::new(MockTransport::new([vec![0x00, 0x0a]]), 200);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: false,
+                    percent: 45
+                })
+            );
+        }
+    }
+}
+
+pub mod aerox5 {
+    use super::steelseries::{decode_battery, AEROX_9};
+    use super::{
+        with_report_id, HidDevice, HidError, HidTransport, Mouse, MouseState, RawBattery,
+        DEFAULT_REPORT_ID,
+    };
+
+    // Not yet checked against captured reports, these follow the Aerox 9 layout
+    const OP_BATTERY_REQUEST: u8 = 0x92;
     const FLAG_WIRELESS: u8 = 0b01000000;
+    /// Offset of the battery byte in a response
+    const OFFSET_BATTERY: usize = 1;
+    const OP_BATTERY_RESPONSE_LEN: usize = OFFSET_BATTERY + 1;
 
-    fn battery_status_from_response(data: u8) -> Option<BatteryStatus> {
-        let percent = u16::from(data & !FLAG_BATTERY_CHARGING).checked_sub(1)? * 5;
-        if percent == 630 {
-            None
+    /// Sends a battery request and decodes the response like the Aerox 9 does
+    fn battery_raw<T: HidTransport>(
+        device: &T,
+        report_id: Option<u8>,
+        request: u8,
+        read_timeout: i32,
+    ) -> Result<RawBattery, HidError> {
+        // First, write the request
+        device.write(&with_report_id(report_id, &[request]))?;
+        // Then, read a response
+        let mut response = [0; OP_BATTERY_RESPONSE_LEN];
+        let len = device.read_timeout(&mut response, read_timeout)?;
+        // Nothing or only part of a response arrived before the timeout
+        let status = if len < OP_BATTERY_RESPONSE_LEN {
+            MouseState::NoResponse
         } else {
-            Some(BatteryStatus {
-                is_charging: data & FLAG_BATTERY_CHARGING != 0,
-                percent,
-            })
+            // Extract fields
+            decode_battery(response[OFFSET_BATTERY], AEROX_9)
+        };
+        Ok(RawBattery {
+            response: response[..len].to_vec(),
+            status,
+        })
+    }
+
+    pub struct Wired<T = HidDevice> {
+        device: T,
+        read_timeout: i32,
+        /// Written before each request
+        report_id: Option<u8>,
+    }
+    impl<T: HidTransport> Wired<T> {
+        /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+        pub fn new(device: T, read_timeout: i32) -> Self {
+            Wired {
+                device,
+                read_timeout,
+                report_id: DEFAULT_REPORT_ID,
+            }
+        }
+
+        /// Writes requests with another report id than [`DEFAULT_REPORT_ID`], or none
+        pub fn with_report_id(self, report_id: Option<u8>) -> Self {
+            Wired { report_id, ..self }
         }
     }
+    impl<T: HidTransport> Mouse for Wired<T> {
+        fn battery(&self) -> Result<MouseState, HidError> {
+            Ok(self.battery_raw()?.status)
+        }
+
+        fn battery_raw(&self) -> Result<RawBattery, HidError> {
+            battery_raw(
+                &self.device,
+                self.report_id,
+                OP_BATTERY_REQUEST,
+                self.read_timeout,
+            )
+        }
+    }
+    pub struct Wireless<T = HidDevice> {
+        device: T,
+        read_timeout: i32,
+        /// Written before each request
+        report_id: Option<u8>,
+    }
+    impl<T: HidTransport> Wireless<T> {
+        /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+        pub fn new(device: T, read_timeout: i32) -> Self {
+            Wireless {
+                device,
+                read_timeout,
+                report_id: DEFAULT_REPORT_ID,
+            }
+        }
+
+        /// Writes requests with another report id than [`DEFAULT_REPORT_ID`], or none
+        pub fn with_report_id(self, report_id: Option<u8>) -> Self {
+            Wireless { report_id, ..self }
+        }
+    }
+    impl<T: HidTransport> Mouse for Wireless<T> {
+        fn battery(&self) -> Result<MouseState, HidError> {
+            Ok(self.battery_raw()?.status)
+        }
+
+        fn battery_raw(&self) -> Result<RawBattery, HidError> {
+            battery_raw(
+                &self.device,
+                self.report_id,
+                OP_BATTERY_REQUEST | FLAG_WIRELESS,
+                self.read_timeout,
+            )
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mouse::mock::MockTransport;
+        use crate::mouse::BatteryStatus;
+
+        #[test]
+        fn wireless_request() {
+            let mouse = Wireless::new(MockTransport::new([vec![0x00, 0x8a]]), 200);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: true,
+                    percent: 45
+                })
+            );
+            assert_eq!(mouse.device.written(), [vec![0x00, 0xd2]]);
+        }
+    }
+}
+
+pub mod prime {
+    use super::steelseries::{decode_battery, DecodeConfig, AEROX_9};
+    use super::{HidDevice, HidError, HidTransport, Mouse, MouseState, RawBattery};
 
-    pub struct Wired {
-        device: HidDevice,
+    // Not yet checked against captured reports
+    pub const OP_BATTERY_REQUEST: u8 = 0xaa;
+    const OP_BATTERY_RESPONSE_LEN: usize = 2;
+    /// Laid out like the Aerox 9, with the charging flag one bit lower
+    pub const BATTERY: DecodeConfig = DecodeConfig {
+        charging: 0b01000000,
+        ..AEROX_9
+    };
+
+    pub struct Wireless<T = HidDevice> {
+        device: T,
+        read_timeout: i32,
+    }
+    impl<T: HidTransport> Wireless<T> {
+        /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+        pub fn new(device: T, read_timeout: i32) -> Self {
+            Wireless {
+                device,
+                read_timeout,
+            }
+        }
     }
-    impl Mouse for Wired {
-        fn new(device: HidDevice) -> Self {
-            Wired { device }
+    impl<T: HidTransport> Mouse for Wireless<T> {
+        fn battery(&self) -> Result<MouseState, HidError> {
+            Ok(self.battery_raw()?.status)
         }
-        fn battery(&self) -> Result<Option<BatteryStatus>, HidError> {
+
+        fn battery_raw(&self) -> Result<RawBattery, HidError> {
             // First, write the request
             self.device.write(&[0x00, OP_BATTERY_REQUEST])?;
             // Then, read a response
             let mut response = [0; OP_BATTERY_RESPONSE_LEN];
-            self.device.read_timeout(&mut response, 200)?;
-            // Extract fields
-            Ok(battery_status_from_response(response[1]))
+            let len = self.device.read_timeout(&mut response, self.read_timeout)?;
+            // Nothing or only part of a response arrived before the timeout
+            let status = if len < OP_BATTERY_RESPONSE_LEN {
+                MouseState::NoResponse
+            } else {
+                // Extract fields
+                decode_battery(response[1], BATTERY)
+            };
+            Ok(RawBattery {
+                response: response[..len].to_vec(),
+                status,
+            })
         }
     }
-    pub struct Wireless {
-        device: HidDevice,
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mouse::mock::MockTransport;
+        use crate::mouse::BatteryStatus;
+
+        #[test]
+        fn wireless_request() {
+            let mouse = Wireless::new(MockTransport::new([vec![0x00, 0x4a]]), 200);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: true,
+                    percent: 45
+                })
+            );
+            assert_eq!(mouse.device.written(), [vec![0x00, 0xaa]]);
+        }
     }
-    impl Mouse for Wireless {
-        fn new(device: HidDevice) -> Self {
-            Wireless { device }
+}
+
+pub mod rival3 {
+    use super::{BatteryStatus, HidDevice, HidError, HidTransport, Mouse, MouseState, RawBattery};
+
+    /// Battery query, followed by its sub-command
+    const OP_BATTERY_REQUEST: [u8; 2] = [0xaa, 0x01];
+    /// Report id, battery percent, then flags
+    const OP_BATTERY_RESPONSE_LEN: usize = 3;
+    const FLAG_BATTERY_CHARGING: u8 = 0b00000001;
+
+    /// Decodes the battery percent and flag bytes of a response
+    ///
+    /// Unlike the Aerox the percent is reported directly, so it only needs clamping
+    pub fn battery_status_from_response(percent: u8, flags: u8) -> BatteryStatus {
+        BatteryStatus {
+            is_charging: flags & FLAG_BATTERY_CHARGING != 0,
+            percent: u16::from(percent.min(100)),
+        }
+    }
+
+    pub struct Wireless<T = HidDevice> {
+        device: T,
+        read_timeout: i32,
+    }
+    impl<T: HidTransport> Wireless<T> {
+        /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+        pub fn new(device: T, read_timeout: i32) -> Self {
+            Wireless {
+                device,
+                read_timeout,
+            }
         }
-        fn battery(&self) -> Result<Option<BatteryStatus>, HidError> {
+    }
+    impl<T: HidTransport> Mouse for Wireless<T> {
+        fn battery(&self) -> Result<MouseState, HidError> {
+            Ok(self.battery_raw()?.status)
+        }
+
+        fn battery_raw(&self) -> Result<RawBattery, HidError> {
             // First, write the request
-            self.device
-                .write(&[0x00, OP_BATTERY_REQUEST | FLAG_WIRELESS])?;
+            let [op, sub] = OP_BATTERY_REQUEST;
+            self.device.write(&[0x00, op, sub])?;
             // Then, read a response
             let mut response = [0; OP_BATTERY_RESPONSE_LEN];
-            self.device.read_timeout(&mut response, 200)?;
-            // Extract fields
-            Ok(battery_status_from_response(response[1]))
+            let len = self.device.read_timeout(&mut response, self.read_timeout)?;
+            // Nothing or only part of a response arrived before the timeout
+            let status = if len < OP_BATTERY_RESPONSE_LEN {
+                MouseState::NoResponse
+            } else {
+                // Extract fields
+                MouseState::Battery(battery_status_from_response(response[1], response[2]))
+            };
+            Ok(RawBattery {
+                response: response[..len].to_vec(),
+                status,
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mouse::mock::MockTransport;
+
+        #[test]
+        fn decode() {
+            assert_eq!(
+                battery_status_from_response(45, 0),
+                BatteryStatus {
+                    is_charging: false,
+                    percent: 45
+                }
+            );
+            assert_eq!(
+                battery_status_from_response(45, FLAG_BATTERY_CHARGING),
+                BatteryStatus {
+                    is_charging: true,
+                    percent: 45
+                }
+            );
+        }
+
+        #[test]
+        fn short_read_is_no_response() {
+            for response in [vec![], vec![0x00], vec![0x00, 0x2d]] {
+                let mouse = Wireless::new(MockTransport::new([response]), 200);
+                assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+            }
+            let mouse = Wireless::new(MockTransport::new([vec![0x00, 0x2d, 0x01]]), 200);
+            assert_eq!(
+                mouse.battery().unwrap(),
+                MouseState::Battery(BatteryStatus {
+                    is_charging: true,
+                    percent: 45
+                })
+            );
+        }
+
+        #[test]
+        fn clamps_percent() {
+            assert_eq!(battery_status_from_response(0xff, 0).percent, 100);
         }
     }
 }
 
-pub trait Mouse {
-    fn new(device: HidDevice) -> Self
-    where
-        Self: Sized;
-    fn battery(&self) -> Result<Option<BatteryStatus>, HidError>;
+/// Battery reading for SteelSeries mice without a model of their own
+///
+/// Tries the battery requests of the supported SteelSeries mice in turn and only accepts a
+/// response that decodes to a percentage from 0 to 100, or to the level the receivers report
+/// while the mouse is asleep. The first request that works is tried first from then on
+pub mod steelseries_generic {
+    use super::steelseries::{decode_battery, DecodeConfig, AEROX_9};
+    use super::{
+        prime, with_report_id, HidDevice, HidError, HidTransport, Mouse, MouseState, RawBattery,
+        DEFAULT_REPORT_ID,
+    };
+    use std::cell::Cell;
+
+    /// Battery requests tried, along with the layout of their answers
+    const REQUESTS: [(u8, DecodeConfig); 3] = [
+        // Aerox 9 and 5 through the receiver, and the Aerox 3
+        (0xd2, AEROX_9),
+        // Aerox 9 and 5 over the cable
+        (0x92, AEROX_9),
+        (prime::OP_BATTERY_REQUEST, prime::BATTERY),
+    ];
+    /// Report id, then the battery byte
+    const RESPONSE_LEN: usize = 2;
+
+    /// Rejects levels no supported mouse reports
+    fn sane(status: MouseState) -> MouseState {
+        match status {
+            MouseState::Battery(battery) if battery.percent > 100 => MouseState::NoResponse,
+            status => status,
+        }
+    }
+
+    pub struct Wireless<T = HidDevice> {
+        device: T,
+        read_timeout: i32,
+        /// Written before each request
+        report_id: Option<u8>,
+        /// Index in [`REQUESTS`] of the request the mouse last answered
+        request: Cell<Option<usize>>,
+    }
+    impl<T: HidTransport> Wireless<T> {
+        /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+        pub fn new(device: T, read_timeout: i32) -> Self {
+            Wireless {
+                device,
+                read_timeout,
+                report_id: DEFAULT_REPORT_ID,
+                request: Cell::new(None),
+            }
+        }
+
+        /// Writes requests with another report id than [`DEFAULT_REPORT_ID`], or none
+        pub fn with_report_id(self, report_id: Option<u8>) -> Self {
+            Wireless { report_id, ..self }
+        }
+
+        /// Sends one of the known requests and decodes its answer
+        fn try_request(&self, index: usize) -> Result<RawBattery, HidError> {
+            let (request, layout) = REQUESTS[index];
+            self.device
+                .write(&with_report_id(self.report_id, &[request]))?;
+            let mut response = [0; RESPONSE_LEN];
+            let len = self.device.read_timeout(&mut response, self.read_timeout)?;
+            // Nothing or only part of a response arrived before the timeout
+            let status = if len < RESPONSE_LEN {
+                MouseState::NoResponse
+            } else {
+                sane(decode_battery(response[1], layout))
+            };
+            Ok(RawBattery {
+                response: response[..len].to_vec(),
+                status,
+            })
+        }
+    }
+    impl<T: HidTransport> Mouse for Wireless<T> {
+        fn battery(&self) -> Result<MouseState, HidError> {
+            Ok(self.battery_raw()?.status)
+        }
+
+        fn battery_raw(&self) -> Result<RawBattery, HidError> {
+            let known = self.request.get();
+            let others = (0..REQUESTS.len()).filter(|&index| Some(index) != known);
+            let mut raw = RawBattery {
+                response: Vec::new(),
+                status: MouseState::NoResponse,
+            };
+            for index in known.into_iter().chain(others) {
+                raw = self.try_request(index)?;
+                if raw.status != MouseState::NoResponse {
+                    self.request.set(Some(index));
+                    break;
+                }
+            }
+            Ok(raw)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mouse::mock::MockTransport;
+        use crate::mouse::BatteryStatus;
+
+        #[test]
+        fn finds_request() {
+            let mouse = Wireless::new(
+                MockTransport::new([vec![], vec![0x00, 0x0a], vec![0x00, 0x09]]),
+                200,
+            );
+            let discharging = |percent| {
+                MouseState::Battery(BatteryStatus {
+                    is_charging: false,
+                    percent,
+                })
+            };
+            assert_eq!(mouse.battery().unwrap(), discharging(45));
+            // The request that worked is sent first next time
+            assert_eq!(mouse.battery().unwrap(), discharging(40));
+            assert_eq!(
+                mouse.device.written(),
+                [vec![0x00, 0xd2], vec![0x00, 0x92], vec![0x00, 0x92]]
+            );
+        }
+
+        #[test]
+        fn rejects_insane_levels() {
+            // Levels above 100% in every layout
+            let mouse = Wireless::new(
+                MockTransport::new([vec![0x00, 0x7e], vec![0x00, 0x7e], vec![0x00, 0x7e]]),
+                200,
+            );
+            assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+            assert_eq!(mouse.device.written().len(), REQUESTS.len());
+        }
+    }
+}
+
+/// The parts of a HID device used to talk to a mouse
+///
+/// Implemented for [`HidDevice`], and by a mock in tests
+pub trait HidTransport: Send {
+    fn write(&self, data: &[u8]) -> Result<usize, HidError>;
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> Result<usize, HidError>;
+    /// Sends a feature report, the first byte being the report id
+    fn send_feature_report(&self, data: &[u8]) -> Result<(), HidError>;
+    /// Reads a feature report, with the report id to read in the first byte of `buf`
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize, HidError>;
+}
+impl HidTransport for HidDevice {
+    fn write(&self, data: &[u8]) -> Result<usize, HidError> {
+        HidDevice::write(self, data)
+    }
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> Result<usize, HidError> {
+        HidDevice::read_timeout(self, buf, timeout)
+    }
+    fn send_feature_report(&self, data: &[u8]) -> Result<(), HidError> {
+        HidDevice::send_feature_report(self, data)
+    }
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize, HidError> {
+        HidDevice::get_feature_report(self, buf)
+    }
+}
+
+/// A mouse driver, `Send` so that reads can run on a blocking thread
+pub trait Mouse: Send {
+    fn battery(&self) -> Result<MouseState, HidError>;
+
+    /// Reads the battery status along with the raw response it was decoded from
+    ///
+    /// Mice that don't override this report an empty response
+    fn battery_raw(&self) -> Result<RawBattery, HidError> {
+        Ok(RawBattery {
+            response: Vec::new(),
+            status: self.battery()?,
+        })
+    }
+
+    /// Reads the current DPI setting, `None` if the model doesn't support querying it
+    fn dpi(&self) -> Result<Option<u16>, HidError> {
+        Ok(None)
+    }
+
+    /// Reads the firmware version, `None` if the model doesn't support querying it
+    fn firmware_version(&self) -> Result<Option<String>, HidError> {
+        Ok(None)
+    }
+
+    /// State of the wireless link as of the last battery read, `None` if the model doesn't
+    /// report it
+    fn connection(&self) -> Option<Connection> {
+        None
+    }
+
+    /// Reads everything the model can report in as few round trips as it can
+    ///
+    /// Mice that don't override this only read the battery
+    fn poll_all(&self) -> Result<DeviceStatus, HidError> {
+        Ok(DeviceStatus {
+            battery: self.battery()?,
+            dpi: None,
+            connection: self.connection(),
+        })
+    }
+}
+
+/// What a mouse reported in one [`Mouse::poll_all`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceStatus {
+    pub battery: MouseState,
+    /// DPI setting, `None` if the model doesn't read it along with the battery
+    pub dpi: Option<u16>,
+    /// State of the wireless link, `None` if the model doesn't report it
+    pub connection: Option<Connection>,
 }
-#[derive(Debug)]
+
+/// Whether a battery reading came from the mouse itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connection {
+    /// The mouse answered through the receiver
+    Connected,
+    /// The receiver answered with the last level it had, the mouse may not be in range
+    Stale,
+}
+
+/// A battery status along with the bytes it was decoded from, for debugging new models
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawBattery {
+    /// Bytes read from the device
+    pub response: Vec<u8>,
+    pub status: MouseState,
+}
+
+/// What a mouse reported when asked for its battery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseState {
+    /// The mouse reported its battery
+    Battery(BatteryStatus),
+    /// The receiver is connected but the mouse is off or asleep
+    Asleep,
+    /// The mouse gave no usable response, e.g. because it did not answer in time
+    NoResponse,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BatteryStatus {
     pub is_charging: bool,
     pub percent: u16,
 }
+
+/// Scripted transport for testing code built on the drivers without a device
+#[cfg(any(test, feature = "mock"))]
+pub mod mock {
+    use super::{HidError, HidTransport};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Transport that records writes and replies with canned responses
+    ///
+    /// Feature reports share the same record and responses as interrupt reports
+    pub struct MockTransport {
+        written: RefCell<Vec<Vec<u8>>>,
+        /// Responses still to come, `None` failing the read
+        responses: RefCell<VecDeque<Option<Vec<u8>>>>,
+    }
+    impl MockTransport {
+        /// Creates a transport that answers reads with `responses`, in order
+        ///
+        /// An empty response behaves like a read that timed out
+        pub fn new(responses: impl IntoIterator<Item = Vec<u8>>) -> Self {
+            Self::failing(responses.into_iter().map(Some))
+        }
+
+        /// Creates a transport like [`MockTransport::new`] whose reads fail with an error where
+        /// a response is `None`
+        pub fn failing(responses: impl IntoIterator<Item = Option<Vec<u8>>>) -> Self {
+            MockTransport {
+                written: RefCell::new(Vec::new()),
+                responses: RefCell::new(responses.into_iter().collect()),
+            }
+        }
+
+        /// Every buffer written so far
+        pub fn written(&self) -> Vec<Vec<u8>> {
+            self.written.borrow().clone()
+        }
+    }
+    impl HidTransport for MockTransport {
+        fn write(&self, data: &[u8]) -> Result<usize, HidError> {
+            self.written.borrow_mut().push(data.to_vec());
+            Ok(data.len())
+        }
+        fn read_timeout(&self, buf: &mut [u8], _timeout: i32) -> Result<usize, HidError> {
+            self.get_feature_report(buf)
+        }
+        fn send_feature_report(&self, data: &[u8]) -> Result<(), HidError> {
+            self.write(data).map(|_| ())
+        }
+        fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize, HidError> {
+            let response = match self.responses.borrow_mut().pop_front() {
+                Some(Some(response)) => response,
+                Some(None) => {
+                    return Err(HidError::HidApiError {
+                        message: "read failed".into(),
+                    })
+                }
+                None => {
+                    return Err(HidError::HidApiError {
+                        message: "no response left".into(),
+                    })
+                }
+            };
+            let len = response.len().min(buf.len());
+            buf[..len].copy_from_slice(&response[..len]);
+            Ok(len)
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+
+//! Corsair mice
+//!
+//! Corsair's wireless mice are driven through 64 byte interrupt reports. The host sends a
+//! command byte addressed to the mouse behind the receiver rather than the receiver itself, and
+//! the answer echoes the command followed by an error code. The battery query answers with the
+//! percentage directly, followed by the charging state.
+use super::{BatteryStatus, HidDevice, HidError, HidTransport, Mouse, MouseState, RawBattery};
+
+/// Length of a report, excluding the report id
+const REPORT_LEN: usize = 64;
+/// Addresses a request to the mouse behind the receiver
+const ENDPOINT_WIRELESS: u8 = 0x09;
+/// Reads a property
+const CMD_GET: u8 = 0x02;
+/// Battery percentage and charging state
+const PROPERTY_BATTERY: u8 = 0x0f;
+/// Charging states
+const STATUS_CHARGING: u8 = 1;
+const STATUS_DISCHARGING: u8 = 2;
+/// Charged and still plugged in
+const STATUS_FULL: u8 = 3;
+/// Offsets of the fields in a response
+const OFFSET_COMMAND: usize = 1;
+const OFFSET_ERROR: usize = 2;
+const OFFSET_PERCENT: usize = 3;
+const OFFSET_STATUS: usize = 4;
+
+/// Builds the battery request, excluding the report id
+pub fn request() -> [u8; REPORT_LEN] {
+    let mut report = [0; REPORT_LEN];
+    report[0] = ENDPOINT_WIRELESS;
+    report[OFFSET_COMMAND] = CMD_GET;
+    report[2] = PROPERTY_BATTERY;
+    report
+}
+
+/// Decodes the answer to the battery request
+pub fn battery_from_response(response: &[u8]) -> MouseState {
+    if response.len() <= OFFSET_STATUS
+        || response[OFFSET_COMMAND] != CMD_GET
+        || response[OFFSET_ERROR] != 0
+    {
+        return MouseState::NoResponse;
+    }
+    let percent = response[OFFSET_PERCENT];
+    if percent > 100 {
+        return MouseState::NoResponse;
+    }
+    let is_charging = match response[OFFSET_STATUS] {
+        STATUS_CHARGING | STATUS_FULL => true,
+        STATUS_DISCHARGING => false,
+        _ => return MouseState::NoResponse,
+    };
+    MouseState::Battery(BatteryStatus {
+        is_charging,
+        percent: u16::from(percent),
+    })
+}
+
+/// A Corsair Dark Core RGB Pro, through its receiver
+pub struct Wireless<T = HidDevice> {
+    device: T,
+    read_timeout: i32,
+}
+impl<T: HidTransport> Wireless<T> {
+    /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+    pub fn new(device: T, read_timeout: i32) -> Self {
+        Wireless {
+            device,
+            read_timeout,
+        }
+    }
+}
+impl<T: HidTransport> Mouse for Wireless<T> {
+    fn battery(&self) -> Result<MouseState, HidError> {
+        Ok(self.battery_raw()?.status)
+    }
+
+    fn battery_raw(&self) -> Result<RawBattery, HidError> {
+        // Output reports are prefixed by the report id, which is always 0
+        let mut report = [0; REPORT_LEN + 1];
+        report[1..].copy_from_slice(&request());
+        self.device.write(&report)?;
+        let mut response = [0; REPORT_LEN];
+        let len = self.device.read_timeout(&mut response, self.read_timeout)?;
+        let response = response[..len].to_vec();
+        Ok(RawBattery {
+            status: battery_from_response(&response),
+            response,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mouse::mock::MockTransport;
+
+    /// Answer at 46% while discharging, trailing padding cut
+    const DISCHARGING: [u8; 5] = [0x00, 0x02, 0x00, 0x2e, 0x02];
+    /// Answer at 100% while still plugged in
+    const FULL: [u8; 5] = [0x00, 0x02, 0x00, 0x64, 0x03];
+    /// Answer from the receiver while the mouse is off
+    const FAILED: [u8; 5] = [0x00, 0x02, 0x03, 0x00, 0x00];
+
+    #[test]
+    fn decode() {
+        assert_eq!(
+            battery_from_response(&DISCHARGING),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 46
+            })
+        );
+        assert_eq!(
+            battery_from_response(&FULL),
+            MouseState::Battery(BatteryStatus {
+                is_charging: true,
+                percent: 100
+            })
+        );
+        assert_eq!(battery_from_response(&FAILED), MouseState::NoResponse);
+        assert_eq!(
+            battery_from_response(&[0x00, 0x02, 0x00, 0x65, 0x02]),
+            MouseState::NoResponse
+        );
+        assert_eq!(
+            battery_from_response(&[0x00, 0x02, 0x00, 0x2e, 0x00]),
+            MouseState::NoResponse
+        );
+        assert_eq!(
+            battery_from_response(&DISCHARGING[..4]),
+            MouseState::NoResponse
+        );
+    }
+
+    #[test]
+    fn battery_request() {
+        let mouse = Wireless::new(MockTransport::new([DISCHARGING.to_vec()]), 200);
+        assert_eq!(
+            mouse.battery().unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 46
+            })
+        );
+        let written = mouse.device.written();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0][..4], [0x00, 0x09, 0x02, 0x0f]);
+        assert_eq!(written[0].len(), REPORT_LEN + 1);
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+
+//! Glorious mice
+//!
+//! The battery is read through a 64 byte vendor feature report. The request carries a command in
+//! its header, and the device answers with the same report holding the battery percentage and
+//! whether it is charging. The layout follows community captures of the Model O Wireless and has
+//! not been checked against every firmware.
+use super::{BatteryStatus, HidDevice, HidError, HidTransport, Mouse, MouseState, RawBattery};
+use std::time::{Duration, Instant};
+
+/// Length of a report, excluding the report id
+const REPORT_LEN: usize = 64;
+/// Header of the battery request
+const BATTERY_REQUEST: [u8; 6] = [0x00, 0x00, 0x02, 0x02, 0x00, 0x83];
+/// Offsets of the fields in a response
+const OFFSET_STATUS: usize = 0;
+const OFFSET_CHARGING: usize = 6;
+const OFFSET_PERCENT: usize = 7;
+/// Status of a response the receiver answered on behalf of a mouse that is off
+const STATUS_ASLEEP: u8 = 0xa4;
+/// How long to wait between checks for the response to be filled in
+const RESPONSE_DELAY: Duration = Duration::from_millis(50);
+
+/// Decodes a battery response, excluding the report id
+pub fn battery_status_from_response(response: &[u8; REPORT_LEN]) -> MouseState {
+    if response[OFFSET_STATUS] == STATUS_ASLEEP {
+        return MouseState::Asleep;
+    }
+    let percent = response[OFFSET_PERCENT];
+    // Nothing has been filled in
+    if percent == 0 || percent > 100 {
+        return MouseState::NoResponse;
+    }
+    MouseState::Battery(BatteryStatus {
+        is_charging: response[OFFSET_CHARGING] == 1,
+        percent: percent.into(),
+    })
+}
+
+/// A Glorious Model O Wireless, through its receiver or cable
+pub struct Wireless<T = HidDevice> {
+    device: T,
+    read_timeout: i32,
+}
+impl<T: HidTransport> Wireless<T> {
+    /// Wraps a device, waiting at most `read_timeout` milliseconds for the response
+    pub fn new(device: T, read_timeout: i32) -> Self {
+        Wireless {
+            device,
+            read_timeout,
+        }
+    }
+}
+impl<T: HidTransport> Mouse for Wireless<T> {
+    fn battery(&self) -> Result<MouseState, HidError> {
+        Ok(self.battery_raw()?.status)
+    }
+
+    fn battery_raw(&self) -> Result<RawBattery, HidError> {
+        // Feature reports are prefixed by the report id, which is always 0
+        let mut request = [0; REPORT_LEN + 1];
+        request[1..=BATTERY_REQUEST.len()].copy_from_slice(&BATTERY_REQUEST);
+        self.device.send_feature_report(&request)?;
+        let read_timeout = u64::try_from(self.read_timeout).unwrap_or(0);
+        let deadline = Instant::now() + Duration::from_millis(read_timeout);
+        loop {
+            std::thread::sleep(RESPONSE_DELAY);
+            let mut response = [0; REPORT_LEN + 1];
+            let len = self.device.get_feature_report(&mut response)?;
+            let status = if len < REPORT_LEN + 1 {
+                MouseState::NoResponse
+            } else {
+                let mut report = [0; REPORT_LEN];
+                report.copy_from_slice(&response[1..]);
+                battery_status_from_response(&report)
+            };
+            // A full response that is not filled in yet is checked again until the deadline
+            if status != MouseState::NoResponse
+                || len < REPORT_LEN + 1
+                || Instant::now() >= deadline
+            {
+                return Ok(RawBattery {
+                    response: response[..len].to_vec(),
+                    status,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mouse::mock::MockTransport;
+
+    fn response(charging: u8, percent: u8) -> [u8; REPORT_LEN] {
+        let mut response = [0; REPORT_LEN];
+        response[..BATTERY_REQUEST.len()].copy_from_slice(&BATTERY_REQUEST);
+        response[OFFSET_CHARGING] = charging;
+        response[OFFSET_PERCENT] = percent;
+        response
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(
+            battery_status_from_response(&response(0, 45)),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 45
+            })
+        );
+        assert_eq!(
+            battery_status_from_response(&response(1, 100)),
+            MouseState::Battery(BatteryStatus {
+                is_charging: true,
+                percent: 100
+            })
+        );
+        assert_eq!(
+            battery_status_from_response(&response(0, 0)),
+            MouseState::NoResponse
+        );
+        let mut asleep = response(0, 45);
+        asleep[OFFSET_STATUS] = STATUS_ASLEEP;
+        assert_eq!(battery_status_from_response(&asleep), MouseState::Asleep);
+    }
+
+    #[test]
+    fn feature_report_request() {
+        let mut reply = vec![0x00];
+        reply.extend_from_slice(&response(1, 80));
+        let mouse = Wireless::new(MockTransport::new([reply]), 200);
+        assert_eq!(
+            mouse.battery().unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: true,
+                percent: 80
+            })
+        );
+        let written = mouse.device.written();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].len(), REPORT_LEN + 1);
+        assert_eq!(written[0][1..=BATTERY_REQUEST.len()], BATTERY_REQUEST);
+    }
+
+    #[test]
+    fn waits_for_response() {
+        let mut empty = vec![0x00];
+        empty.extend_from_slice(&response(0, 0));
+        let mut reply = vec![0x00];
+        reply.extend_from_slice(&response(0, 45));
+        let mouse = Wireless::new(MockTransport::new([empty.clone(), reply]), 200);
+        assert_eq!(
+            mouse.battery().unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 45
+            })
+        );
+        assert_eq!(mouse.device.written().len(), 1);
+
+        // Gives up once the read timeout is over
+        let mouse = Wireless::new(MockTransport::new([empty]), 0);
+        assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+    }
+}
@@ -0,0 +1,394 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+
+//! Logitech mice speaking HID++ 2.0
+//!
+//! Every HID++ 2.0 request addresses a feature by its index in the device's feature table,
+//! which differs between devices. The index of the battery feature is looked up through the
+//! root feature on the first battery read and cached afterwards.
+use super::{BatteryStatus, HidDevice, HidError, HidTransport, Mouse, MouseState};
+use std::cell::Cell;
+
+/// Report id of a 7 byte HID++ report
+const REPORT_SHORT: u8 = 0x10;
+const REPORT_SHORT_LEN: usize = 7;
+/// Report id of a 20 byte HID++ report
+const REPORT_LONG: u8 = 0x11;
+const REPORT_LONG_LEN: usize = 20;
+/// Device index of the first device paired to a receiver
+const DEVICE_INDEX_RECEIVER: u8 = 0x01;
+/// Sub id a receiver answers with if the request failed, e.g. because the mouse is off
+const HIDPP10_ERROR: u8 = 0x8f;
+/// HID++ 1.0 error a receiver reports when the paired device can't be reached
+const HIDPP10_ERROR_RESOURCE: u8 = 0x09;
+/// Feature index a device answers with if the request failed
+const HIDPP20_ERROR: u8 = 0xff;
+/// Identifies our requests among the device's responses
+const SOFTWARE_ID: u8 = 0x0a;
+/// How many unrelated reports to skip while waiting for a response
+const MAX_SKIPPED_REPORTS: usize = 8;
+
+/// The root feature always lives at index 0
+const ROOT_INDEX: u8 = 0x00;
+/// Root feature function mapping a feature id to its index
+const ROOT_GET_FEATURE: u8 = 0x0;
+/// Battery level status, reporting the percentage directly
+const FEATURE_BATTERY_STATUS: u16 = 0x1000;
+/// Battery voltage, used by mice like the G Pro Wireless
+const FEATURE_BATTERY_VOLTAGE: u16 = 0x1001;
+/// Function 0 of both battery features reads the battery
+const BATTERY_GET_STATUS: u8 = 0x0;
+/// Battery voltage flag set while the mouse is charging
+const FLAG_VOLTAGE_CHARGING: u8 = 0b10000000;
+
+/// Approximate charge of a single cell lithium battery at a given voltage in millivolts
+const VOLTAGE_CURVE: [(u16, u16); 11] = [
+    (4186, 100),
+    (4067, 90),
+    (3989, 80),
+    (3922, 70),
+    (3859, 60),
+    (3811, 50),
+    (3778, 40),
+    (3751, 30),
+    (3717, 20),
+    (3671, 10),
+    (3500, 0),
+];
+
+/// Parameters of a response, or what to report if the device did not give one
+type Reply = Result<[u8; 16], MouseState>;
+
+/// Battery feature supported by a device, along with its index in the feature table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatteryFeature {
+    Status(u8),
+    Voltage(u8),
+}
+
+/// Decodes the response to a battery level status request
+///
+/// The first byte is the percentage and the third the charging state, where 1 to 4 are all
+/// flavours of being plugged in
+pub fn battery_status_from_status(params: &[u8]) -> BatteryStatus {
+    BatteryStatus {
+        is_charging: matches!(params[2], 1..=4),
+        percent: u16::from(params[0].min(100)),
+    }
+}
+
+/// Decodes the response to a battery voltage request
+///
+/// The first two bytes are the voltage in millivolts, which is mapped onto [`VOLTAGE_CURVE`]
+pub fn battery_status_from_voltage(params: &[u8]) -> BatteryStatus {
+    let voltage = u16::from_be_bytes([params[0], params[1]]);
+    BatteryStatus {
+        is_charging: params[2] & FLAG_VOLTAGE_CHARGING != 0,
+        percent: percent_from_voltage(voltage),
+    }
+}
+
+/// Linearly interpolates the charge percentage between points of [`VOLTAGE_CURVE`]
+fn percent_from_voltage(voltage: u16) -> u16 {
+    let (max_voltage, max_percent) = VOLTAGE_CURVE[0];
+    if voltage >= max_voltage {
+        return max_percent;
+    }
+    for pair in VOLTAGE_CURVE.windows(2) {
+        let [(high_voltage, high_percent), (low_voltage, low_percent)] = [pair[0], pair[1]];
+        if voltage >= low_voltage {
+            let scaled = u32::from(voltage - low_voltage) * u32::from(high_percent - low_percent)
+                / u32::from(high_voltage - low_voltage);
+            // The scaled value is bounded by the percent gap, which fits in a u16
+            return low_percent + scaled as u16;
+        }
+    }
+    0
+}
+
+/// A HID++ 2.0 mouse connected through a receiver
+pub struct Wireless<T = HidDevice> {
+    device: T,
+    read_timeout: i32,
+    /// Battery feature found on the first read
+    feature: Cell<Option<BatteryFeature>>,
+}
+impl<T: HidTransport> Wireless<T> {
+    /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+    pub fn new(device: T, read_timeout: i32) -> Self {
+        Wireless {
+            device,
+            read_timeout,
+            feature: Cell::new(None),
+        }
+    }
+
+    /// Sends a short request and waits for the matching response
+    ///
+    /// Fails with [`MouseState::Asleep`] if the receiver could not reach the mouse, or
+    /// [`MouseState::NoResponse`] if the device reported another error or did not answer in time
+    fn request(&self, feature_index: u8, function: u8, params: [u8; 3]) -> Result<Reply, HidError> {
+        let function = function << 4 | SOFTWARE_ID;
+        let [param0, param1, param2] = params;
+        self.device.write(&[
+            REPORT_SHORT,
+            DEVICE_INDEX_RECEIVER,
+            feature_index,
+            function,
+            param0,
+            param1,
+            param2,
+        ])?;
+        for _ in 0..MAX_SKIPPED_REPORTS {
+            let mut response = [0; REPORT_LONG_LEN];
+            let len = self.device.read_timeout(&mut response, self.read_timeout)?;
+            // Nothing arrived before the timeout
+            if len == 0 {
+                return Ok(Err(MouseState::NoResponse));
+            }
+            let report_len = match response[0] {
+                REPORT_SHORT => REPORT_SHORT_LEN,
+                REPORT_LONG => REPORT_LONG_LEN,
+                _ => continue,
+            };
+            // Skip truncated reports and reports from other devices
+            if len < report_len || response[1] != DEVICE_INDEX_RECEIVER {
+                continue;
+            }
+            match response[2] {
+                // The receiver could not reach the mouse
+                HIDPP10_ERROR
+                    if response[3] == feature_index
+                        && response[4] == function
+                        && response[5] == HIDPP10_ERROR_RESOURCE =>
+                {
+                    return Ok(Err(MouseState::Asleep))
+                }
+                // Our request failed
+                HIDPP10_ERROR | HIDPP20_ERROR
+                    if response[3] == feature_index && response[4] == function =>
+                {
+                    return Ok(Err(MouseState::NoResponse))
+                }
+                index
+                    if index == feature_index
+                        && response[0] == REPORT_LONG
+                        && response[3] == function =>
+                {
+                    let mut params = [0; 16];
+                    params.copy_from_slice(&response[4..]);
+                    return Ok(Ok(params));
+                }
+                // Some other report, like a notification
+                _ => continue,
+            }
+        }
+        Ok(Err(MouseState::NoResponse))
+    }
+
+    /// Looks up the index of a feature in the device's feature table
+    ///
+    /// An index of 0 means the feature is not supported
+    fn feature_index(&self, feature: u16) -> Result<Result<u8, MouseState>, HidError> {
+        let [high, low] = feature.to_be_bytes();
+        let reply = self.request(ROOT_INDEX, ROOT_GET_FEATURE, [high, low, 0])?;
+        Ok(reply.map(|params| params[0]))
+    }
+
+    /// Finds which battery feature the device supports, caching the result
+    ///
+    /// Fails with the state to report if the device did not answer, e.g. because the mouse is off
+    fn battery_feature(&self) -> Result<Result<BatteryFeature, MouseState>, HidError> {
+        if let Some(feature) = self.feature.get() {
+            return Ok(Ok(feature));
+        }
+        let feature = match self.feature_index(FEATURE_BATTERY_STATUS)? {
+            Err(state) => return Ok(Err(state)),
+            Ok(0) => match self.feature_index(FEATURE_BATTERY_VOLTAGE)? {
+                Err(state) => return Ok(Err(state)),
+                Ok(0) => {
+                    return Err(HidError::HidApiError {
+                        message: "device has no supported HID++ battery feature".into(),
+                    })
+                }
+                Ok(index) => BatteryFeature::Voltage(index),
+            },
+            Ok(index) => BatteryFeature::Status(index),
+        };
+        self.feature.set(Some(feature));
+        Ok(Ok(feature))
+    }
+}
+impl<T: HidTransport> Mouse for Wireless<T> {
+    fn battery(&self) -> Result<MouseState, HidError> {
+        let feature = match self.battery_feature()? {
+            Ok(feature) => feature,
+            Err(state) => return Ok(state),
+        };
+        let (index, decode): (u8, fn(&[u8]) -> BatteryStatus) = match feature {
+            BatteryFeature::Status(index) => (index, battery_status_from_status),
+            BatteryFeature::Voltage(index) => (index, battery_status_from_voltage),
+        };
+        Ok(match self.request(index, BATTERY_GET_STATUS, [0; 3])? {
+            Ok(params) => MouseState::Battery(decode(&params)),
+            Err(state) => state,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mouse::mock::MockTransport;
+
+    /// Builds a long response to one of our requests
+    fn response(feature_index: u8, function: u8, params: &[u8]) -> Vec<u8> {
+        let mut response = vec![
+            REPORT_LONG,
+            DEVICE_INDEX_RECEIVER,
+            feature_index,
+            function << 4 | SOFTWARE_ID,
+        ];
+        response.extend_from_slice(params);
+        response.resize(REPORT_LONG_LEN, 0);
+        response
+    }
+
+    #[test]
+    fn voltage() {
+        assert_eq!(percent_from_voltage(4200), 100);
+        assert_eq!(percent_from_voltage(3811), 50);
+        assert_eq!(percent_from_voltage(3835), 55);
+        assert_eq!(percent_from_voltage(3400), 0);
+        assert_eq!(
+            battery_status_from_voltage(&[0x0e, 0xe3, FLAG_VOLTAGE_CHARGING]),
+            BatteryStatus {
+                is_charging: true,
+                percent: 50
+            }
+        );
+    }
+
+    #[test]
+    fn caches_feature_index() {
+        let mouse = Wireless::new(
+            MockTransport::new([
+                // Battery level status is at index 6
+                response(ROOT_INDEX, ROOT_GET_FEATURE, &[0x06]),
+                response(0x06, BATTERY_GET_STATUS, &[55, 50, 0]),
+                response(0x06, BATTERY_GET_STATUS, &[50, 45, 1]),
+            ]),
+            200,
+        );
+        assert_eq!(
+            mouse.battery().unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 55
+            })
+        );
+        assert_eq!(
+            mouse.battery().unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: true,
+                percent: 50
+            })
+        );
+        assert_eq!(
+            mouse.device.written(),
+            [
+                vec![0x10, 0x01, 0x00, 0x0a, 0x10, 0x00, 0x00],
+                vec![0x10, 0x01, 0x06, 0x0a, 0x00, 0x00, 0x00],
+                vec![0x10, 0x01, 0x06, 0x0a, 0x00, 0x00, 0x00],
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_voltage() {
+        let mouse = Wireless::new(
+            MockTransport::new([
+                // No battery level status, battery voltage is at index 4
+                response(ROOT_INDEX, ROOT_GET_FEATURE, &[0x00]),
+                response(ROOT_INDEX, ROOT_GET_FEATURE, &[0x04]),
+                response(0x04, BATTERY_GET_STATUS, &[0x0e, 0xe3, 0x00]),
+            ]),
+            200,
+        );
+        assert_eq!(
+            mouse.battery().unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 50
+            })
+        );
+        assert_eq!(mouse.feature.get(), Some(BatteryFeature::Voltage(0x04)));
+    }
+
+    #[test]
+    fn mouse_off() {
+        let mouse = Wireless::new(
+            MockTransport::new([vec![
+                REPORT_SHORT,
+                DEVICE_INDEX_RECEIVER,
+                HIDPP10_ERROR,
+                ROOT_INDEX,
+                ROOT_GET_FEATURE << 4 | SOFTWARE_ID,
+                HIDPP10_ERROR_RESOURCE,
+                0x00,
+            ]]),
+            200,
+        );
+        assert_eq!(mouse.battery().unwrap(), MouseState::Asleep);
+        assert_eq!(mouse.feature.get(), None);
+    }
+
+    #[test]
+    fn skips_mismatched_reports() {
+        let mut truncated = response(ROOT_INDEX, ROOT_GET_FEATURE, &[0x06]);
+        truncated.truncate(REPORT_SHORT_LEN);
+        let mut other_device = response(ROOT_INDEX, ROOT_GET_FEATURE, &[0x05]);
+        other_device[1] = 0x02;
+        let mut other_report = response(ROOT_INDEX, ROOT_GET_FEATURE, &[0x05]);
+        other_report[0] = 0x20;
+        let mouse = Wireless::new(
+            MockTransport::new([
+                truncated,
+                other_device,
+                other_report,
+                response(0x04, ROOT_GET_FEATURE, &[0x05]),
+                response(ROOT_INDEX, ROOT_GET_FEATURE, &[0x06]),
+                response(0x06, BATTERY_GET_STATUS, &[55, 50, 0]),
+            ]),
+            200,
+        );
+        assert_eq!(
+            mouse.battery().unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 55
+            })
+        );
+        assert_eq!(mouse.feature.get(), Some(BatteryFeature::Status(0x06)));
+    }
+
+    #[test]
+    fn truncated_response() {
+        let mut truncated = response(ROOT_INDEX, ROOT_GET_FEATURE, &[0x06]);
+        truncated.truncate(REPORT_SHORT_LEN);
+        let mouse = Wireless::new(
+            // The read times out after the truncated report
+            MockTransport::new([truncated, Vec::new()]),
+            200,
+        );
+        assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+        assert_eq!(mouse.feature.get(), None);
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+
+//! Razer mice
+//!
+//! Razer devices are driven through 90 byte feature reports. Each report carries a transaction
+//! id specific to the device, a command class and id, up to 80 bytes of arguments and a
+//! checksum. The device answers a command by updating the status byte of the report it hands
+//! back.
+use super::{BatteryStatus, HidDevice, HidError, HidTransport, Mouse, MouseState};
+use std::time::{Duration, Instant};
+
+/// Length of a report, excluding the report id
+pub const REPORT_LEN: usize = 90;
+/// Number of argument bytes in a report
+pub const ARGUMENTS_LEN: usize = 80;
+/// Offsets of the fields in a report
+const OFFSET_STATUS: usize = 0;
+const OFFSET_TRANSACTION_ID: usize = 1;
+const OFFSET_DATA_SIZE: usize = 5;
+const OFFSET_COMMAND_CLASS: usize = 6;
+const OFFSET_COMMAND_ID: usize = 7;
+const OFFSET_ARGUMENTS: usize = 8;
+const OFFSET_CRC: usize = 88;
+/// Status of a report sent to the device
+const STATUS_NEW: u8 = 0x00;
+/// Status the device reports while it is still working on a command
+const STATUS_BUSY: u8 = 0x01;
+/// Status the device reports once a command succeeded
+const STATUS_SUCCESS: u8 = 0x02;
+/// Status a receiver reports when the mouse did not answer, e.g. because it is off
+const STATUS_TIMEOUT: u8 = 0x04;
+/// How long to wait between checks on a busy device
+const BUSY_DELAY: Duration = Duration::from_millis(10);
+
+/// A command understood by a Razer device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub class: u8,
+    pub id: u8,
+    /// Number of argument bytes the command uses
+    pub data_size: u8,
+}
+
+/// Device specific parts of the protocol
+#[derive(Debug)]
+pub struct Model {
+    pub transaction_id: u8,
+    /// Reads the battery level as 0 to 255 in the second argument byte
+    pub battery_level: Command,
+    /// Reads whether the mouse is charging as 0 or 1 in the second argument byte
+    pub charging_status: Command,
+}
+
+pub const DEATHADDER_V2_PRO: Model = Model {
+    transaction_id: 0x3f,
+    battery_level: Command {
+        class: 0x07,
+        id: 0x80,
+        data_size: 0x02,
+    },
+    charging_status: Command {
+        class: 0x07,
+        id: 0x84,
+        data_size: 0x02,
+    },
+};
+
+/// The Viper Ultimate answers the same battery commands, with the same transaction id, as the
+/// DeathAdder V2 Pro, as in OpenRazer's driver
+pub const VIPER_ULTIMATE: Model = DEATHADDER_V2_PRO;
+
+/// Checksum of a report, the XOR of every byte between the status header and the checksum
+pub fn crc(report: &[u8; REPORT_LEN]) -> u8 {
+    report[2..OFFSET_CRC].iter().fold(0, |crc, byte| crc ^ byte)
+}
+
+/// Builds a report sending a command without arguments
+pub fn request(transaction_id: u8, command: Command) -> [u8; REPORT_LEN] {
+    let mut report = [0; REPORT_LEN];
+    report[OFFSET_STATUS] = STATUS_NEW;
+    report[OFFSET_TRANSACTION_ID] = transaction_id;
+    report[OFFSET_DATA_SIZE] = command.data_size;
+    report[OFFSET_COMMAND_CLASS] = command.class;
+    report[OFFSET_COMMAND_ID] = command.id;
+    report[OFFSET_CRC] = crc(&report);
+    report
+}
+
+/// Maps the 0 to 255 battery level onto a percentage, rounding to the nearest percent
+pub fn percent_from_level(level: u8) -> u16 {
+    (u16::from(level) * 100 + 127) / 255
+}
+
+/// A wireless Razer mouse
+pub struct Wireless<T = HidDevice> {
+    device: T,
+    read_timeout: i32,
+    model: &'static Model,
+}
+impl<T: HidTransport> Wireless<T> {
+    /// Wraps a device, waiting at most `read_timeout` milliseconds for each response
+    pub fn new(device: T, read_timeout: i32, model: &'static Model) -> Self {
+        Wireless {
+            device,
+            read_timeout,
+            model,
+        }
+    }
+
+    /// Sends a command and waits for the device to finish it
+    ///
+    /// Returns the arguments of the response, or the state to report if the device failed the
+    /// command or took too long
+    fn command(
+        &self,
+        command: Command,
+    ) -> Result<Result<[u8; ARGUMENTS_LEN], MouseState>, HidError> {
+        // Feature reports are prefixed by the report id, which is always 0
+        let mut report = [0; REPORT_LEN + 1];
+        report[1..].copy_from_slice(&request(self.model.transaction_id, command));
+        self.device.send_feature_report(&report)?;
+        let read_timeout = u64::try_from(self.read_timeout).unwrap_or(0);
+        let deadline = Instant::now() + Duration::from_millis(read_timeout);
+        loop {
+            std::thread::sleep(BUSY_DELAY);
+            let mut response = [0; REPORT_LEN + 1];
+            let len = self.device.get_feature_report(&mut response)?;
+            // The device answers with the report we sent, with an updated status
+            let response = &response[1..];
+            if len < REPORT_LEN + 1
+                || response[OFFSET_COMMAND_CLASS] != command.class
+                || response[OFFSET_COMMAND_ID] != command.id
+            {
+                return Ok(Err(MouseState::NoResponse));
+            }
+            match response[OFFSET_STATUS] {
+                STATUS_SUCCESS => {
+                    let mut arguments = [0; ARGUMENTS_LEN];
+                    arguments.copy_from_slice(&response[OFFSET_ARGUMENTS..OFFSET_CRC]);
+                    return Ok(Ok(arguments));
+                }
+                STATUS_BUSY if Instant::now() < deadline => {}
+                STATUS_TIMEOUT => return Ok(Err(MouseState::Asleep)),
+                _ => return Ok(Err(MouseState::NoResponse)),
+            }
+        }
+    }
+}
+impl<T: HidTransport> Mouse for Wireless<T> {
+    fn battery(&self) -> Result<MouseState, HidError> {
+        let level = match self.command(self.model.battery_level)? {
+            Ok(level) => level,
+            Err(state) => return Ok(state),
+        };
+        let charging = match self.command(self.model.charging_status)? {
+            Ok(charging) => charging,
+            Err(state) => return Ok(state),
+        };
+        Ok(MouseState::Battery(BatteryStatus {
+            is_charging: charging[1] != 0,
+            percent: percent_from_level(level[1]),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mouse::mock::MockTransport;
+
+    /// Builds the feature report a device sends back for one of our requests
+    fn response(command: Command, status: u8, argument: u8) -> Vec<u8> {
+        let mut response = vec![0];
+        response.extend_from_slice(&request(DEATHADDER_V2_PRO.transaction_id, command));
+        response[1 + OFFSET_STATUS] = status;
+        response[1 + OFFSET_ARGUMENTS + 1] = argument;
+        response
+    }
+
+    /// The feature report we send for a command
+    fn sent(command: Command) -> Vec<u8> {
+        let mut report = vec![0];
+        report.extend_from_slice(&request(DEATHADDER_V2_PRO.transaction_id, command));
+        report
+    }
+
+    #[test]
+    fn battery_level_request() {
+        let report = request(0x3f, DEATHADDER_V2_PRO.battery_level);
+        assert_eq!(
+            report[..8],
+            [0x00, 0x3f, 0x00, 0x00, 0x00, 0x02, 0x07, 0x80]
+        );
+        assert_eq!(report[OFFSET_CRC], 0x02 ^ 0x07 ^ 0x80);
+        assert_eq!(crc(&report), report[OFFSET_CRC]);
+    }
+
+    #[test]
+    fn viper_ultimate_requests() {
+        let report = request(VIPER_ULTIMATE.transaction_id, VIPER_ULTIMATE.battery_level);
+        assert_eq!(
+            report[..8],
+            [0x00, 0x3f, 0x00, 0x00, 0x00, 0x02, 0x07, 0x80]
+        );
+        assert_eq!(report[OFFSET_CRC], 0x02 ^ 0x07 ^ 0x80);
+        let report = request(
+            VIPER_ULTIMATE.transaction_id,
+            VIPER_ULTIMATE.charging_status,
+        );
+        assert_eq!(
+            report[..8],
+            [0x00, 0x3f, 0x00, 0x00, 0x00, 0x02, 0x07, 0x84]
+        );
+        assert_eq!(report[OFFSET_CRC], 0x02 ^ 0x07 ^ 0x84);
+    }
+
+    #[test]
+    fn level() {
+        assert_eq!(percent_from_level(0), 0);
+        assert_eq!(percent_from_level(128), 50);
+        assert_eq!(percent_from_level(255), 100);
+    }
+
+    #[test]
+    fn busy_then_success() {
+        let level = DEATHADDER_V2_PRO.battery_level;
+        let charging = DEATHADDER_V2_PRO.charging_status;
+        let mouse = Wireless::new(
+            MockTransport::new([
+                response(level, STATUS_BUSY, 0),
+                response(level, STATUS_SUCCESS, 128),
+                response(charging, STATUS_SUCCESS, 1),
+            ]),
+            200,
+            &DEATHADDER_V2_PRO,
+        );
+        assert_eq!(
+            mouse.battery().unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: true,
+                percent: 50
+            })
+        );
+        assert_eq!(mouse.device.written(), [sent(level), sent(charging)]);
+    }
+
+    #[test]
+    fn busy_past_deadline() {
+        let level = DEATHADDER_V2_PRO.battery_level;
+        let mouse = Wireless::new(
+            MockTransport::new([response(level, STATUS_BUSY, 0)]),
+            0,
+            &DEATHADDER_V2_PRO,
+        );
+        assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+    }
+
+    #[test]
+    fn timeout() {
+        let level = DEATHADDER_V2_PRO.battery_level;
+        let mouse = Wireless::new(
+            MockTransport::new([response(level, STATUS_TIMEOUT, 0)]),
+            200,
+            &DEATHADDER_V2_PRO,
+        );
+        assert_eq!(mouse.battery().unwrap(), MouseState::Asleep);
+        assert_eq!(mouse.device.written(), [sent(level)]);
+    }
+
+    #[test]
+    fn failure() {
+        let level = DEATHADDER_V2_PRO.battery_level;
+        let charging = DEATHADDER_V2_PRO.charging_status;
+        let mouse = Wireless::new(
+            MockTransport::new([
+                response(level, STATUS_SUCCESS, 128),
+                // Failed
+                response(charging, 0x03, 0),
+            ]),
+            200,
+            &DEATHADDER_V2_PRO,
+        );
+        assert_eq!(mouse.battery().unwrap(), MouseState::NoResponse);
+        assert_eq!(mouse.device.written(), [sent(level), sent(charging)]);
+    }
+}
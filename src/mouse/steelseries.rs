@@ -0,0 +1,126 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+
+//! Battery decoding shared by SteelSeries mice
+//!
+//! SteelSeries mice answer a battery request with a single byte holding a charging flag and a
+//! scaled level. Models differ in where the flag sits and how the level is scaled.
+use super::{BatteryStatus, MouseState};
+
+/// Layout of the battery byte of a model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeConfig {
+    /// Bit set while the mouse is charging, every other bit is the level
+    pub charging: u8,
+    /// Percent per step of the level
+    pub scale: u16,
+    /// Level reported at 0%, lower levels are not a usable response
+    pub offset: u16,
+    /// Percentage reported while the mouse is off or asleep, if any
+    pub sentinel: Option<u16>,
+}
+
+/// The low 7 bits hold the level as `percent / 5 + 1` and the high bit is the charging flag.
+/// The out of range level 630 is what the receiver reports while the mouse is off or asleep
+pub const AEROX_9: DecodeConfig = DecodeConfig {
+    charging: 0b10000000,
+    scale: 5,
+    offset: 1,
+    sentinel: Some(630),
+};
+
+/// Decodes the battery byte of a response
+pub fn decode_battery(data: u8, cfg: DecodeConfig) -> MouseState {
+    let Some(level) = u16::from(data & !cfg.charging).checked_sub(cfg.offset) else {
+        return MouseState::NoResponse;
+    };
+    let percent = level * cfg.scale;
+    if Some(percent) == cfg.sentinel {
+        MouseState::Asleep
+    } else {
+        MouseState::Battery(BatteryStatus {
+            is_charging: data & cfg.charging != 0,
+            percent,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_level_is_no_response() {
+        assert_eq!(decode_battery(0x00, AEROX_9), MouseState::NoResponse);
+        assert_eq!(
+            decode_battery(AEROX_9.charging, AEROX_9),
+            MouseState::NoResponse
+        );
+    }
+
+    #[test]
+    fn discharging() {
+        assert_eq!(
+            decode_battery(0x0a, AEROX_9),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 45
+            })
+        );
+        assert_eq!(
+            decode_battery(0x15, AEROX_9),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 100
+            })
+        );
+    }
+
+    #[test]
+    fn charging() {
+        assert_eq!(
+            decode_battery(0x0a | AEROX_9.charging, AEROX_9),
+            MouseState::Battery(BatteryStatus {
+                is_charging: true,
+                percent: 45
+            })
+        );
+    }
+
+    #[test]
+    fn sentinel_is_asleep() {
+        assert_eq!(decode_battery(0x7f, AEROX_9), MouseState::Asleep);
+        assert_eq!(decode_battery(0xff, AEROX_9), MouseState::Asleep);
+    }
+
+    #[test]
+    fn custom_layout() {
+        let cfg = DecodeConfig {
+            charging: 0b01000000,
+            scale: 1,
+            offset: 0,
+            sentinel: None,
+        };
+        assert_eq!(
+            decode_battery(0x40 | 45, cfg),
+            MouseState::Battery(BatteryStatus {
+                is_charging: true,
+                percent: 45
+            })
+        );
+        assert_eq!(
+            decode_battery(0x00, cfg),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 0
+            })
+        );
+    }
+}
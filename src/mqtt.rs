@@ -0,0 +1,218 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use std::str::FromStr;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+use tracing::{debug, error, warn};
+
+/// Port used when the broker address doesn't name one
+const DEFAULT_PORT: u16 = 1883;
+/// Number of messages queued while the broker is unreachable
+const QUEUE_LEN: usize = 64;
+/// How long to wait before reconnecting to the broker
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How long to wait for the last messages to be sent on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Prefix Home Assistant listens for discovery messages on
+const DISCOVERY_PREFIX: &str = "homeassistant";
+/// Payloads of the availability topics
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// Host and port of an MQTT broker
+#[derive(Debug, Clone)]
+pub struct Broker {
+    host: String,
+    port: u16,
+}
+impl FromStr for Broker {
+    type Err = BrokerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, port) = match s.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| BrokerError::Port)?),
+            None => (s, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err(BrokerError::Host);
+        }
+        Ok(Broker {
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+#[derive(Debug, thiserror::Error)]
+pub enum BrokerError {
+    #[error("missing host")]
+    Host,
+    #[error("port must be a number between 0 and 65535")]
+    Port,
+}
+
+/// Maps the QoS given on the command line to its MQTT level
+pub fn qos(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+/// Client id used when none is given, unique to this process so that several instances sharing
+/// a broker don't keep disconnecting each other
+pub fn default_client_id(topic: &str) -> String {
+    format!("{}_{}", object_id(topic), std::process::id())
+}
+
+/// Publishes battery readings to an MQTT broker, announcing each mouse to Home Assistant
+///
+/// Every mouse has a state topic with its JSON status and an availability topic. A status topic
+/// shared by every mouse goes offline through the broker's last will if nezumi dies.
+pub struct Publisher {
+    client: AsyncClient,
+    event_loop: JoinHandle<()>,
+    /// Prefix of every topic published to
+    topic: String,
+    qos: QoS,
+    /// Whether battery readings are retained, discovery and availability always are
+    retain: bool,
+}
+impl Publisher {
+    /// Starts connecting to the broker in the background
+    pub fn connect(
+        broker: &Broker,
+        client_id: &str,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+    ) -> Self {
+        let status_topic = format!("{topic}/status");
+        let mut options = MqttOptions::new(client_id, &broker.host, broker.port);
+        options
+            .set_keep_alive(Duration::from_secs(30))
+            .set_last_will(LastWill::new(&status_topic, OFFLINE, qos, true));
+        let (client, mut event_loop) = AsyncClient::new(options, QUEUE_LEN);
+        let status_client = client.clone();
+        let event_loop = tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    // Announce ourselves again after every reconnect, replacing the last will
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        debug!("Connected to MQTT broker");
+                        if let Err(err) =
+                            status_client.try_publish(&status_topic, qos, true, ONLINE)
+                        {
+                            error!("Error publishing MQTT status: {err}");
+                        }
+                    }
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!("MQTT connection failed: {err}");
+                        time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+        Publisher {
+            client,
+            event_loop,
+            topic,
+            qos,
+            retain,
+        }
+    }
+
+    /// Announces a mouse to Home Assistant as a battery sensor
+    pub fn announce(&self, name: &str, model: &str) {
+        let id = object_id(name);
+        let config = serde_json::json!({
+            "name": "Battery",
+            "unique_id": format!("nezumi_{id}_battery"),
+            "state_topic": self.state_topic(name),
+            "value_template": "{{ value_json.percent }}",
+            "json_attributes_topic": self.state_topic(name),
+            "unit_of_measurement": "%",
+            "device_class": "battery",
+            "state_class": "measurement",
+            "availability": [
+                { "topic": format!("{}/status", self.topic) },
+                { "topic": self.availability_topic(name) },
+            ],
+            "availability_mode": "all",
+            "device": {
+                "identifiers": [format!("nezumi_{id}")],
+                "name": name,
+                "model": model,
+            },
+        });
+        self.publish(
+            &format!("{DISCOVERY_PREFIX}/sensor/nezumi_{id}/battery/config"),
+            true,
+            config.to_string(),
+        );
+    }
+
+    /// Publishes the JSON status of a mouse
+    pub fn state(&self, name: &str, json: String) {
+        self.publish(&self.state_topic(name), self.retain, json);
+    }
+
+    /// Marks a mouse as connected or gone
+    pub fn availability(&self, name: &str, online: bool) {
+        let payload = if online { ONLINE } else { OFFLINE };
+        self.publish(&self.availability_topic(name), true, payload);
+    }
+
+    /// Marks nezumi as offline and disconnects from the broker
+    pub async fn shutdown(self) {
+        let status_topic = format!("{}/status", self.topic);
+        if let Err(err) = self
+            .client
+            .try_publish(status_topic, self.qos, true, OFFLINE)
+        {
+            error!("Error publishing MQTT status: {err}");
+        }
+        if let Err(err) = self.client.try_disconnect() {
+            error!("Error disconnecting from MQTT broker: {err}");
+        }
+        if time::timeout(SHUTDOWN_TIMEOUT, self.event_loop)
+            .await
+            .is_err()
+        {
+            warn!("Timed out disconnecting from MQTT broker");
+        }
+    }
+
+    /// Profile names may contain `/`, `+` or `#`, which would change the topic or make it invalid
+    fn state_topic(&self, name: &str) -> String {
+        format!("{}/{}/state", self.topic, object_id(name))
+    }
+
+    fn availability_topic(&self, name: &str) -> String {
+        format!("{}/{}/availability", self.topic, object_id(name))
+    }
+
+    /// Queues a message without waiting for the broker
+    fn publish(&self, topic: &str, retain: bool, payload: impl Into<Vec<u8>>) {
+        if let Err(err) = self.client.try_publish(topic, self.qos, retain, payload) {
+            error!("Error publishing to MQTT topic {topic}: {err}");
+        }
+    }
+}
+
+/// Turns a profile name into something Home Assistant accepts in ids and MQTT in topic levels
+fn object_id(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
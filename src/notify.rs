@@ -0,0 +1,80 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use nezumi::mouse::BatteryStatus;
+use nezumi::thresholds::{Level, Thresholds};
+use notify_rust::{Notification, Urgency};
+use tracing::error;
+
+/// How urgent a low battery notification is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Severity {
+    Low,
+    Critical,
+}
+
+/// Sends a desktop notification when a mouse's battery runs low
+///
+/// Each severity is only notified once until the battery charges back above the low threshold
+#[derive(Debug)]
+pub struct LowBatteryNotifier {
+    thresholds: Thresholds,
+    /// Most severe notification sent since the battery was last above the low threshold
+    sent: Option<Severity>,
+}
+
+impl LowBatteryNotifier {
+    pub fn new(thresholds: Thresholds) -> Self {
+        LowBatteryNotifier {
+            thresholds,
+            sent: None,
+        }
+    }
+
+    /// Notifies if the mouse's battery just became low or critical
+    pub fn update(&mut self, name: &str, status: &BatteryStatus) {
+        // Re-arm once the battery has charged back up
+        if status.percent > self.thresholds.low {
+            self.sent = None;
+            return;
+        }
+        let severity = match self.thresholds.classify(status.percent, status.is_charging) {
+            Level::Critical => Severity::Critical,
+            Level::Low => Severity::Low,
+            Level::Normal | Level::Charging | Level::Full => return,
+        };
+        if self.sent.is_none_or(|sent| severity > sent) {
+            self.sent = Some(severity);
+            notify(name, status.percent, severity);
+        }
+    }
+}
+
+/// Shows a notification without blocking the caller on D-Bus
+fn notify(name: &str, percent: u16, severity: Severity) {
+    let mut notification = Notification::new();
+    notification
+        .appname("nezumi")
+        .body(&format!("{name} is at {percent}%"));
+    match severity {
+        Severity::Low => notification
+            .summary("Mouse battery low")
+            .icon("battery-low")
+            .urgency(Urgency::Normal),
+        Severity::Critical => notification
+            .summary("Mouse battery critically low")
+            .icon("battery-caution")
+            .urgency(Urgency::Critical),
+    };
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = notification.show() {
+            error!("Error showing low battery notification: {err}");
+        }
+    });
+}
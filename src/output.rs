@@ -0,0 +1,126 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Seek, Write};
+use std::os::fd::{BorrowedFd, RawFd};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Where status lines are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputSink {
+    Stdout,
+    File(PathBuf),
+    /// A file descriptor inherited from the parent process
+    Fd(RawFd),
+}
+impl FromStr for OutputSink {
+    type Err = OutputSinkError;
+
+    /// Parses `-` as stdout, `fd:N` as a file descriptor and anything else as a path
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" {
+            return Ok(OutputSink::Stdout);
+        }
+        match s.strip_prefix("fd:") {
+            Some(fd) => match fd.parse() {
+                Ok(fd) if fd >= 0 => Ok(OutputSink::Fd(fd)),
+                _ => Err(OutputSinkError::Fd),
+            },
+            None if s.is_empty() => Err(OutputSinkError::Empty),
+            None => Ok(OutputSink::File(PathBuf::from(s))),
+        }
+    }
+}
+impl fmt::Display for OutputSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputSink::Stdout => f.write_str("stdout"),
+            OutputSink::File(path) => write!(f, "{}", path.display()),
+            OutputSink::Fd(fd) => write!(f, "fd {fd}"),
+        }
+    }
+}
+#[derive(Debug, thiserror::Error)]
+pub enum OutputSinkError {
+    #[error("expected `-`, `fd:N` or a path")]
+    Empty,
+    #[error("file descriptor must be a non-negative number")]
+    Fd,
+}
+
+/// An opened [`OutputSink`], flushed after every line
+pub enum Output {
+    /// Lines are written one after another
+    Stream(BufWriter<Box<dyn Write>>),
+    /// The file only ever holds the latest line
+    Rewrite(BufWriter<File>),
+}
+impl Output {
+    /// Opens a sink, appending to a file if `append` is set and otherwise rewriting it with
+    /// each line
+    pub fn open(sink: &OutputSink, append: bool) -> io::Result<Self> {
+        Ok(match sink {
+            OutputSink::Stdout => Output::Stream(BufWriter::new(Box::new(io::stdout()))),
+            OutputSink::File(path) if append => Output::Stream(BufWriter::new(Box::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            ))),
+            OutputSink::File(path) => Output::Rewrite(BufWriter::new(
+                OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .open(path)?,
+            )),
+            OutputSink::Fd(fd) => {
+                // SAFETY: the descriptor is only borrowed long enough to duplicate it, which fails
+                // if it isn't open
+                let fd = unsafe { BorrowedFd::borrow_raw(*fd) }.try_clone_to_owned()?;
+                Output::Stream(BufWriter::new(Box::new(File::from(fd))))
+            }
+        })
+    }
+
+    /// Writes a line and flushes it
+    pub fn line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Output::Stream(writer) => {
+                writeln!(writer, "{line}")?;
+                writer.flush()
+            }
+            Output::Rewrite(writer) => {
+                let file = writer.get_mut();
+                file.set_len(0)?;
+                file.rewind()?;
+                writeln!(writer, "{line}")?;
+                writer.flush()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("-".parse::<OutputSink>().unwrap(), OutputSink::Stdout);
+        assert_eq!("fd:3".parse::<OutputSink>().unwrap(), OutputSink::Fd(3));
+        assert_eq!(
+            "/run/user/1000/mouse".parse::<OutputSink>().unwrap(),
+            OutputSink::File(PathBuf::from("/run/user/1000/mouse"))
+        );
+        assert!("fd:-1".parse::<OutputSink>().is_err());
+        assert!("fd:x".parse::<OutputSink>().is_err());
+        assert!("".parse::<OutputSink>().is_err());
+    }
+}
@@ -0,0 +1,567 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+
+//! Polling opened mice
+//!
+//! [`OpenMouse`] keeps the state of a mouse between reads, such as the discharge history and the
+//! filters applied to its readings. [`poll_due`] polls a set of them on their schedules, and
+//! [`poll_once`] reads one once.
+use crate::config::MouseProfile;
+use crate::device::{blocking, Node};
+use crate::format::{Formatter, Reading};
+use crate::history::{self, ChargingDebounce, History, Smoothing};
+use crate::jitter::Jitter;
+use crate::mouse::{BatteryStatus, Connection, DeviceStatus, Mouse, MouseState};
+use crate::output::Output;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, error, info, instrument, warn};
+
+/// Settings for how each mouse is polled
+pub struct PollOptions {
+    /// Only print a reading when it differs from the previous one
+    pub on_change: bool,
+    /// How many times to retry a failed battery read
+    pub read_retries: u32,
+    /// Number of readings the percentage is averaged over
+    pub smooth: usize,
+    /// Number of consecutive readings a change of the charging state must be seen in
+    pub charging_reads: usize,
+    /// Read the DPI setting along with the battery
+    pub show_dpi: bool,
+}
+
+/// Delay before the first retry of a failed battery read
+pub const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// A mouse that has been opened and is being polled
+pub struct OpenMouse<'a> {
+    /// Name of the profile the mouse was opened with
+    pub name: &'a str,
+    /// Profile the mouse was opened with
+    pub profile: &'a MouseProfile,
+    /// Driver of the mouse, shared with the blocking thread reading from it
+    mouse: Arc<Mutex<Box<dyn Mouse>>>,
+    /// Last emitted state, used to suppress duplicates with --on-change
+    last_status: Option<MouseState>,
+    /// Recent readings, used to estimate the time remaining
+    history: History,
+    /// Recent percentages, averaged with `--smooth`
+    smoothing: Option<Smoothing>,
+    /// Charging state confirmed over `--charging-reads` readings
+    charging: Option<ChargingDebounce>,
+    /// DPI setting at the last read, if requested and supported
+    dpi: Option<u16>,
+    /// Link state reported along with the last battery read
+    connection: Option<Connection>,
+    /// How long the last battery read took
+    read_duration: Option<Duration>,
+    /// When the battery was last read successfully, and what it read
+    last_battery: Option<(Instant, BatteryStatus)>,
+    /// Charging frame shown with the next emission
+    frame: usize,
+    /// When the battery is next due to be checked
+    pub next_poll: Instant,
+    /// Device node the mouse was opened on, if known
+    pub node: Option<Node>,
+    /// Whether the mouse was charging at the last successful read
+    was_charging: Option<bool>,
+}
+impl<'a> OpenMouse<'a> {
+    pub fn new(name: &'a str, profile: &'a MouseProfile, mouse: Box<dyn Mouse>) -> Self {
+        OpenMouse {
+            name,
+            profile,
+            mouse: Arc::new(Mutex::new(mouse)),
+            last_status: None,
+            history: History::default(),
+            smoothing: None,
+            charging: None,
+            dpi: None,
+            connection: None,
+            read_duration: None,
+            last_battery: None,
+            frame: 0,
+            next_poll: Instant::now(),
+            node: None,
+            was_charging: None,
+        }
+    }
+
+    /// When the battery was last read successfully, and what it read
+    pub fn last_battery(&self) -> Option<(Instant, BatteryStatus)> {
+        self.last_battery
+    }
+
+    /// How long the last battery read took
+    pub fn read_duration(&self) -> Option<Duration> {
+        self.read_duration
+    }
+
+    /// Link state reported along with the last battery read
+    pub fn connection(&self) -> Option<Connection> {
+        self.connection
+    }
+
+    /// Runs blocking I/O on the mouse on the blocking thread pool
+    pub async fn with_mouse<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&dyn Mouse) -> T + Send + 'static,
+    ) -> T {
+        let mouse = Arc::clone(&self.mouse);
+        blocking(move || f(mouse.lock().unwrap_or_else(|err| err.into_inner()).as_ref())).await
+    }
+
+    /// Replaces the driver of a mouse that was reopened, possibly on another node
+    pub fn replace_mouse(&mut self, mouse: Box<dyn Mouse>, node: Node) {
+        self.mouse = Arc::new(Mutex::new(mouse));
+        self.node = Some(node);
+    }
+
+    /// Reads the status of the mouse, retrying up to `retries` times on errors
+    ///
+    /// The delay between attempts doubles after each failure. How long the successful read
+    /// took is returned too
+    async fn read(&self, retries: u32) -> Result<(DeviceStatus, Duration), hidapi::HidError> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            match self
+                .with_mouse(|mouse| {
+                    // Timed on the blocking thread so waiting for it isn't counted
+                    let start = std::time::Instant::now();
+                    let status = mouse.poll_all()?;
+                    Ok((status, start.elapsed()))
+                })
+                .await
+            {
+                Err(err) if attempt < retries => {
+                    warn!(
+                        "Error reading battery status of {}, retrying in {backoff:?}: {err}",
+                        self.name
+                    );
+                    time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Reads the battery status of the mouse and prints it
+    pub async fn poll(
+        &mut self,
+        formatter: &Formatter,
+        output: &mut Output,
+        options: &PollOptions,
+    ) -> Result<MouseState, hidapi::HidError> {
+        let state = self.update(options).await?;
+        self.emit(state, formatter, output, options);
+        Ok(state)
+    }
+
+    /// Reads the battery status of the mouse without printing it
+    #[instrument(name = "mouse", skip_all, fields(name = self.name, model = %self.profile.model))]
+    pub async fn update(&mut self, options: &PollOptions) -> Result<MouseState, hidapi::HidError> {
+        // Get the battery status of the mouse
+        let (device_status, duration) = self.read(options.read_retries).await?;
+        debug!("Battery read took {duration:?}");
+        let mut state = device_status.battery;
+        if let MouseState::Battery(status) = &mut state {
+            debug!(
+                "Battery at {}%, charging: {}",
+                status.percent, status.is_charging
+            );
+            *status = self
+                .charging
+                .get_or_insert_with(|| ChargingDebounce::new(options.charging_reads))
+                .debounce(*status);
+            *status = self
+                .smoothing
+                .get_or_insert_with(|| Smoothing::new(options.smooth))
+                .smooth(*status);
+        }
+        self.record(state, device_status, duration, options).await;
+        Ok(state)
+    }
+
+    /// Keeps a state read from the mouse, along with what else the read reported
+    async fn record(
+        &mut self,
+        state: MouseState,
+        device_status: DeviceStatus,
+        duration: Duration,
+        options: &PollOptions,
+    ) {
+        self.connection = device_status.connection;
+        self.read_duration = Some(duration);
+        match state {
+            MouseState::Battery(status) => {
+                self.history.push(Instant::now().into_std(), &status);
+                self.last_battery = Some((Instant::now(), status));
+                if let Some(dpi) = device_status.dpi.filter(|_| options.show_dpi) {
+                    self.dpi = Some(dpi);
+                } else if options.show_dpi {
+                    // The model doesn't read the DPI along with the battery
+                    self.dpi = self
+                        .with_mouse(|mouse| mouse.dpi())
+                        .await
+                        .unwrap_or_else(|err| {
+                            warn!("Error reading DPI of {}: {err}", self.name);
+                            None
+                        });
+                }
+            }
+            MouseState::Asleep => debug!("{} is off or asleep", self.name),
+            MouseState::NoResponse => {
+                warn!("Error in response from {}, will try again", self.name);
+            }
+        }
+    }
+
+    /// Prints a state of the mouse
+    #[instrument(name = "mouse", skip_all, fields(name = self.name, model = %self.profile.model))]
+    pub fn emit(
+        &mut self,
+        state: MouseState,
+        formatter: &Formatter,
+        output: &mut Output,
+        options: &PollOptions,
+    ) {
+        // Skip output if nothing changed since the last emission
+        if !options.on_change || self.last_status != Some(state) {
+            self.last_status = Some(state);
+            self.print(state, formatter, output);
+        }
+    }
+
+    /// Shows the last state again with the next charging frame, if it has one
+    pub fn animate(&mut self, formatter: &Formatter, output: &mut Output) {
+        if let Some(state) = self.last_status.filter(|state| formatter.animates(*state)) {
+            self.print(state, formatter, output);
+        }
+    }
+
+    /// Formats and writes a state, advancing the charging frame
+    fn print(&mut self, state: MouseState, formatter: &Formatter, output: &mut Output) {
+        match formatter.format(&self.reading(state)) {
+            Ok(Some(line)) => {
+                if let Err(err) = output.line(&line) {
+                    error!("Error writing battery status: {err}");
+                }
+            }
+            Ok(None) => {}
+            Err(err) => error!("Error formatting battery status: {err}"),
+        }
+        self.frame = if formatter.animates(state) {
+            self.frame.wrapping_add(1)
+        } else {
+            0
+        };
+    }
+
+    /// Runs the profile's hook if the mouse started or stopped charging since the last read
+    #[instrument(name = "mouse", skip_all, fields(name = self.name, model = %self.profile.model))]
+    pub fn check_charging(&mut self, status: &BatteryStatus) {
+        let Some(was_charging) = self.was_charging.replace(status.is_charging) else {
+            return;
+        };
+        let hook = match (was_charging, status.is_charging) {
+            (false, true) => {
+                info!("{} started charging at {}%", self.name, status.percent);
+                self.profile.on_charge_start.as_deref()
+            }
+            (true, false) => {
+                info!("{} stopped charging at {}%", self.name, status.percent);
+                self.profile.on_charge_stop.as_deref()
+            }
+            _ => None,
+        };
+        if let Some([program, args @ ..]) = hook {
+            // Spawned without waiting, the runtime reaps the process once it exits
+            let spawned = tokio::process::Command::new(program)
+                .args(args)
+                .arg(status.percent.to_string())
+                .spawn();
+            if let Err(err) = spawned {
+                error!("Error running charging hook {program}: {err}");
+            }
+        }
+    }
+
+    /// Describes a state of this mouse for formatting
+    pub fn reading(&self, state: MouseState) -> Reading<'_> {
+        Reading {
+            name: self.name,
+            model: &self.profile.model,
+            state,
+            time_remaining: self.history.time_remaining(),
+            dpi: self.dpi,
+            connection: self.connection,
+            stale: false,
+            frame: self.frame,
+        }
+    }
+}
+
+/// What the caller of [`poll_due`] does with the outcome of each read
+// Only used generically, so the future not being `Send` doesn't matter
+#[allow(async_fn_in_trait)]
+pub trait Events {
+    /// A mouse was read
+    fn record(&mut self, open_mouse: &mut OpenMouse<'_>, state: MouseState);
+    /// Reading a mouse failed
+    fn read_error(&mut self, open_mouse: &OpenMouse<'_>, err: &hidapi::HidError);
+    /// Opens a mouse that failed to read again, returning `None` if it is gone
+    async fn reopen(&mut self, open_mouse: &OpenMouse<'_>) -> Option<(Box<dyn Mouse>, Node)>;
+    /// A mouse is gone and no longer polled
+    fn removed(&mut self, open_mouse: &OpenMouse<'_>);
+}
+
+/// When the mice are polled by [`poll_due`]
+pub struct Schedule {
+    /// Time between reads of a mouse whose profile doesn't set its own
+    pub interval: Duration,
+    /// How much to randomize each interval by
+    pub jitter: Jitter,
+    /// Battery readings left before polling stops, `None` to keep going
+    pub remaining: Option<u64>,
+}
+
+/// Polls every mouse that is due, printing each reading unless `printer` is `None`
+///
+/// A mouse that fails to read is reopened, and dropped if that fails too. Returns
+/// [`ControlFlow::Break`] once the schedule has no readings left
+pub async fn poll_due(
+    mice: &mut Vec<OpenMouse<'_>>,
+    schedule: &mut Schedule,
+    mut printer: Option<(&Formatter, &mut Output)>,
+    options: &PollOptions,
+    events: &mut impl Events,
+) -> ControlFlow<()> {
+    // Mice we can no longer read from, dropped after polling the rest
+    let mut failed = Vec::new();
+    for open_mouse in mice.iter_mut() {
+        // Every mouse is polled on its own schedule
+        let now = Instant::now();
+        if open_mouse.next_poll > now {
+            continue;
+        }
+        let interval = open_mouse
+            .profile
+            .interval
+            .map_or(schedule.interval, |interval| {
+                Duration::from_secs(interval.get())
+            });
+        open_mouse.next_poll = now + schedule.jitter.apply(interval);
+        let polled = match &mut printer {
+            Some((formatter, output)) => open_mouse.poll(formatter, output, options).await,
+            None => open_mouse.update(options).await,
+        };
+        match polled {
+            Ok(state) => {
+                events.record(open_mouse, state);
+                if !matches!(state, MouseState::Battery(_)) {
+                    continue;
+                }
+                if let Some(remaining) = &mut schedule.remaining {
+                    *remaining = remaining.saturating_sub(1);
+                    if *remaining == 0 {
+                        return ControlFlow::Break(());
+                    }
+                }
+            }
+            Err(err) => {
+                error!("Error reading battery status of {}: {err}", open_mouse.name);
+                events.read_error(open_mouse, &err);
+                // The device may still be there after a brief glitch
+                match events.reopen(open_mouse).await {
+                    Some((mouse, node)) => open_mouse.replace_mouse(mouse, node),
+                    None => {
+                        events.removed(open_mouse);
+                        if let Some((formatter, output)) = &mut printer {
+                            remove_line(formatter, output, open_mouse.name);
+                        }
+                        failed.push(open_mouse.name);
+                    }
+                }
+            }
+        }
+    }
+    mice.retain(|open_mouse| !failed.contains(&open_mouse.name));
+    ControlFlow::Continue(())
+}
+
+/// Writes the line showing the other mice once one is gone, if the format shows several at once
+pub fn remove_line(formatter: &Formatter, output: &mut Output, name: &str) {
+    match formatter.remove(name) {
+        Some(Ok(line)) => {
+            if let Err(err) = output.line(&line) {
+                error!("Error writing battery status: {err}");
+            }
+        }
+        Some(Err(err)) => error!("Error formatting battery status: {err}"),
+        None => {}
+    }
+}
+
+/// Reads the battery of a mouse up to `samples` times and returns the median of the readings
+/// that came back, to ride out noisy wireless reads
+///
+/// The readings are taken as the mouse reported them, without the charging debounce or the
+/// smoothing of [`OpenMouse::update`], so the median is of what the mouse actually said. A
+/// sample that fails to read is skipped, the error is only returned if every sample failed.
+/// Falls back to the last state read if no reading came back
+#[instrument(name = "mouse", skip_all, fields(name = open_mouse.name, model = %open_mouse.profile.model))]
+pub async fn poll_once(
+    open_mouse: &mut OpenMouse<'_>,
+    options: &PollOptions,
+    samples: usize,
+) -> Result<MouseState, hidapi::HidError> {
+    let mut readings = Vec::new();
+    let mut last = None;
+    let mut failure = None;
+    for sample in 0..samples.max(1) {
+        if sample > 0 {
+            time::sleep(RETRY_BACKOFF).await;
+        }
+        let (device_status, duration) = match open_mouse.read(options.read_retries).await {
+            Ok(read) => read,
+            Err(err) => {
+                warn!(
+                    "Error reading battery status of {}, skipping the sample: {err}",
+                    open_mouse.name
+                );
+                failure = Some(err);
+                continue;
+            }
+        };
+        debug!("Battery read took {duration:?}");
+        if let MouseState::Battery(status) = device_status.battery {
+            debug!(
+                "Battery at {}%, charging: {}",
+                status.percent, status.is_charging
+            );
+            readings.push(status);
+        }
+        last = Some((device_status, duration));
+    }
+    let (device_status, duration) = match (last, failure) {
+        (Some(last), _) => last,
+        (None, Some(err)) => return Err(err),
+        (None, None) => unreachable!("at least one sample is read"),
+    };
+    let state = history::median(&readings).map_or(device_status.battery, MouseState::Battery);
+    open_mouse
+        .record(state, device_status, duration, options)
+        .await;
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mouse::aerox9;
+    use crate::mouse::mock::MockTransport;
+
+    fn profile() -> MouseProfile {
+        toml::from_str(
+            "model = \"steelseries_aerox_9_wireless\"\n\
+             vendor = \"1038\"\nproduct = \"1858\"\nendpoint = 3",
+        )
+        .unwrap()
+    }
+
+    const OPTIONS: PollOptions = PollOptions {
+        on_change: false,
+        read_retries: 0,
+        smooth: 1,
+        charging_reads: 1,
+        show_dpi: false,
+    };
+
+    #[tokio::test]
+    async fn median_of_samples() {
+        let profile = profile();
+        // 45%, a read that timed out, then 5% and 40%
+        let responses = [vec![0xd2, 0x0a], vec![], vec![0xd2, 0x02], vec![0xd2, 0x09]];
+        let mouse = aerox9::Wireless::new(MockTransport::new(responses), 200);
+        let mut open_mouse = OpenMouse::new("wireless", &profile, Box::new(mouse));
+        assert_eq!(
+            poll_once(&mut open_mouse, &OPTIONS, 4).await.unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 40
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn median_of_raw_samples() {
+        let profile = profile();
+        // 45%, then 5% and 40% while charging
+        let responses = [vec![0xd2, 0x0a], vec![0xd2, 0x82], vec![0xd2, 0x89]];
+        let mouse = aerox9::Wireless::new(MockTransport::new(responses), 200);
+        let mut open_mouse = OpenMouse::new("wireless", &profile, Box::new(mouse));
+        // Neither the debounce nor the smoothing would let these readings through as they are
+        let options = PollOptions {
+            smooth: 3,
+            charging_reads: 3,
+            ..OPTIONS
+        };
+        let expected = BatteryStatus {
+            is_charging: true,
+            percent: 40,
+        };
+        assert_eq!(
+            poll_once(&mut open_mouse, &options, 3).await.unwrap(),
+            MouseState::Battery(expected)
+        );
+        assert_eq!(
+            open_mouse.last_battery().map(|(_, status)| status),
+            Some(expected)
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_sample() {
+        let profile = profile();
+        // 45%, a read that failed, then 40%
+        let responses = [Some(vec![0xd2, 0x0a]), None, Some(vec![0xd2, 0x09])];
+        let mouse = aerox9::Wireless::new(MockTransport::failing(responses), 200);
+        let mut open_mouse = OpenMouse::new("wireless", &profile, Box::new(mouse));
+        assert_eq!(
+            poll_once(&mut open_mouse, &OPTIONS, 3).await.unwrap(),
+            MouseState::Battery(BatteryStatus {
+                is_charging: false,
+                percent: 40
+            })
+        );
+        // Only when every sample fails is the oneshot a failure
+        let mouse = aerox9::Wireless::new(MockTransport::failing([None, None]), 200);
+        let mut open_mouse = OpenMouse::new("wireless", &profile, Box::new(mouse));
+        assert!(poll_once(&mut open_mouse, &OPTIONS, 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn no_reading() {
+        let profile = profile();
+        let mouse = aerox9::Wireless::new(MockTransport::new([vec![]]), 200);
+        let mut open_mouse = OpenMouse::new("wireless", &profile, Box::new(mouse));
+        assert_eq!(
+            poll_once(&mut open_mouse, &OPTIONS, 1).await.unwrap(),
+            MouseState::NoResponse
+        );
+        // Running out of responses is a read error
+        assert!(poll_once(&mut open_mouse, &OPTIONS, 1).await.is_err());
+    }
+}
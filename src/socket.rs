@@ -0,0 +1,58 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixListener;
+use tokio::sync::watch;
+use tracing::{debug, error};
+
+/// Latest JSON status of each connected mouse, keyed by profile name
+pub type StatusCache = BTreeMap<String, String>;
+
+/// Binds the status socket, replacing a socket left behind by a previous run
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "path exists and is not a socket",
+            ))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    UnixListener::bind(path)
+}
+
+/// Answers every client with the cached status of each mouse, one JSON object per line
+pub async fn serve(listener: UnixListener, cache: watch::Receiver<StatusCache>) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                error!("Error accepting status socket client: {err}");
+                continue;
+            }
+        };
+        // Don't hold the cache across the write
+        let response = cache
+            .borrow()
+            .values()
+            .fold(String::new(), |response, line| response + line + "\n");
+        if let Err(err) = stream.write_all(response.as_bytes()).await {
+            debug!("Error writing to status socket client: {err}");
+        }
+    }
+}
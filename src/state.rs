@@ -0,0 +1,92 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use nezumi::mouse::BatteryStatus;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Last battery reading of a mouse, as saved in the state file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Saved {
+    pub percent: u16,
+    pub charging: bool,
+    /// When the reading was taken, in seconds since the Unix epoch
+    pub timestamp: u64,
+}
+impl Saved {
+    /// How long ago the reading was taken
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.timestamp))
+    }
+
+    pub fn status(&self) -> BatteryStatus {
+        BatteryStatus {
+            is_charging: self.charging,
+            percent: self.percent,
+        }
+    }
+}
+
+/// File keeping the last battery reading of each profile across restarts
+#[derive(Debug)]
+pub struct StateFile {
+    path: PathBuf,
+    /// Last reading of each profile by name
+    saved: HashMap<String, Saved>,
+}
+
+impl StateFile {
+    /// Loads the saved readings, starting afresh if there are none or they can't be parsed
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let saved = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|err| {
+                warn!("Ignoring invalid state file {}: {err}", path.display());
+                HashMap::new()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(StateFile { path, saved })
+    }
+
+    /// Last saved reading of a profile
+    pub fn get(&self, name: &str) -> Option<Saved> {
+        self.saved.get(name).copied()
+    }
+
+    /// Saves a reading, replacing the file so readers never see it half written
+    pub fn record(&mut self, name: &str, status: &BatteryStatus) -> io::Result<()> {
+        let timestamp = now();
+        self.saved.insert(
+            name.to_owned(),
+            Saved {
+                percent: status.percent,
+                charging: status.is_charging,
+                timestamp,
+            },
+        );
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = self.path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec(&self.saved)?)?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
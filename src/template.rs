@@ -0,0 +1,172 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+use std::str::FromStr;
+
+/// Template reproducing the original human readable output
+pub const DEFAULT_TEMPLATE: &str = "{icon}{charging} {percent}%";
+
+/// A value substituted into a template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// Battery percentage
+    Percent,
+    /// Charging glyph, empty while discharging
+    Charging,
+    /// Model name of the mouse
+    Model,
+    /// Name of the profile the mouse was opened with
+    Name,
+    /// Battery glyph
+    Icon,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "percent" => Placeholder::Percent,
+            "charging" => Placeholder::Charging,
+            "model" => Placeholder::Model,
+            "name" => Placeholder::Name,
+            "icon" => Placeholder::Icon,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Output template with `{placeholder}`s resolved for every reading
+///
+/// Literal braces are written as `{{` and `}}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("unknown placeholder {{{0}}}, expected one of {{percent}}, {{charging}}, {{model}}, {{name}} or {{icon}}")]
+    UnknownPlaceholder(String),
+    #[error("unclosed {{ in template")]
+    Unclosed,
+    #[error("unmatched }} in template, write }}}} for a literal brace")]
+    Unmatched,
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::Unclosed),
+                        }
+                    }
+                    let placeholder = Placeholder::from_name(&name)
+                        .ok_or(TemplateError::UnknownPlaceholder(name))?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                }
+                '}' => return Err(TemplateError::Unmatched),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template { segments })
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE.parse().expect("default template is valid")
+    }
+}
+
+impl Template {
+    /// Renders the template, looking up the value of each placeholder
+    pub fn render(&self, value: impl Fn(Placeholder) -> String) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => out.push_str(literal),
+                Segment::Placeholder(placeholder) => out.push_str(&value(*placeholder)),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let template: Template = "{{{percent}}} {icon}".parse().unwrap();
+        assert_eq!(
+            template.segments,
+            [
+                Segment::Literal("{".into()),
+                Segment::Placeholder(Placeholder::Percent),
+                Segment::Literal("} ".into()),
+                Segment::Placeholder(Placeholder::Icon),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            "{battery}".parse::<Template>(),
+            Err(TemplateError::UnknownPlaceholder("battery".into()))
+        );
+        assert_eq!("{percent".parse::<Template>(), Err(TemplateError::Unclosed));
+        assert_eq!(
+            "percent}".parse::<Template>(),
+            Err(TemplateError::Unmatched)
+        );
+    }
+
+    #[test]
+    fn render() {
+        let rendered = Template::default().render(|placeholder| match placeholder {
+            Placeholder::Percent => "42".into(),
+            Placeholder::Charging => "+".into(),
+            Placeholder::Icon => "B".into(),
+            Placeholder::Model | Placeholder::Name => String::new(),
+        });
+        assert_eq!(rendered, "B+ 42%");
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+
+/// Battery percentages that separate the levels a reading is classified into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Thresholds {
+    /// At or below this the battery is critically low
+    pub critical: u16,
+    /// At or below this the battery is low
+    pub low: u16,
+    /// At or above this a charging battery is full
+    pub full: u16,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            critical: 10,
+            low: 25,
+            full: 100,
+        }
+    }
+}
+
+/// How full a battery is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Critical,
+    Low,
+    Normal,
+    Charging,
+    Full,
+}
+
+impl Thresholds {
+    /// Classifies a reading, a battery only counts as full while still on the charger
+    pub fn classify(&self, percent: u16, charging: bool) -> Level {
+        if charging && percent >= self.full {
+            Level::Full
+        } else if charging {
+            Level::Charging
+        } else if percent <= self.critical {
+            Level::Critical
+        } else if percent <= self.low {
+            Level::Low
+        } else {
+            Level::Normal
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        let thresholds = Thresholds::default();
+        assert_eq!(thresholds.classify(5, false), Level::Critical);
+        assert_eq!(thresholds.classify(10, false), Level::Critical);
+        assert_eq!(thresholds.classify(25, false), Level::Low);
+        assert_eq!(thresholds.classify(50, false), Level::Normal);
+        assert_eq!(thresholds.classify(5, true), Level::Charging);
+        assert_eq!(thresholds.classify(100, true), Level::Full);
+        assert_eq!(thresholds.classify(100, false), Level::Normal);
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+
+//! Live terminal display for `--watch`
+//!
+//! Drawn with plain ANSI escapes on a terminal put into non-canonical mode through termios,
+//! since neither crossterm nor ratatui is a dependency of nezumi
+use linked_hash_map::LinkedHashMap;
+use nezumi::format::{self, Reading};
+use nezumi::mouse::MouseState;
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Stdout, Write};
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, RawFd};
+use std::panic;
+use std::sync::{Mutex, Once};
+use tokio::io::unix::AsyncFd;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::time::{self, Duration, Instant, Interval};
+
+/// Most readings shown in the history sparkline, fewer if the terminal is narrower
+const SPARKLINE_LEN: usize = 40;
+/// Blocks of the sparkline, from empty to full
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// How often the screen is redrawn between readings, to keep the age of the last one current
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+/// Size assumed if the terminal doesn't report one
+const DEFAULT_SIZE: (usize, usize) = (80, 24);
+/// Label in front of the sparkline
+const HISTORY_LABEL: &str = "  History  ";
+
+/// Terminal settings to put back, taken by whichever of [`Drop`] and the panic hook runs first
+static SAVED_TERMIOS: Mutex<Option<libc::termios>> = Mutex::new(None);
+/// Whether the panic hook restoring the terminal has been installed
+static PANIC_HOOK: Once = Once::new();
+
+/// Latest state of a mouse on the screen
+#[derive(Debug)]
+struct Row {
+    model: String,
+    state: MouseState,
+    time_remaining: Option<Duration>,
+    /// When the mouse was last read
+    updated: Instant,
+    /// Most recent battery percentages, oldest first
+    recent: VecDeque<u16>,
+}
+
+/// The terminal while `--watch` draws on it
+///
+/// Input is read a key at a time without echo, and the alternate screen is shown until the
+/// display is dropped. A panic restores the terminal too, even when it aborts
+pub struct Tui {
+    stdout: Stdout,
+    rows: LinkedHashMap<String, Row>,
+    /// Stdin, polled for key presses on the runtime
+    keys: AsyncFd<RawFd>,
+    redraw: Interval,
+    /// Redraws the screen to fit when the terminal is resized
+    resized: Signal,
+}
+impl Tui {
+    /// Takes over the terminal on stdin and stdout, which must both be one
+    pub fn open() -> io::Result<Self> {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return Err(io::Error::other("stdin and stdout must be a terminal"));
+        }
+        let fd = io::stdin().as_raw_fd();
+        let mut termios = MaybeUninit::uninit();
+        // SAFETY: tcgetattr fills in the struct when it succeeds
+        let termios = unsafe {
+            if libc::tcgetattr(fd, termios.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios.assume_init()
+        };
+        let mut raw = termios;
+        // Ctrl-C still shuts down like it does otherwise, but Ctrl-\ would kill us without a
+        // chance to restore the terminal
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        raw.c_cc[libc::VQUIT] = libc::_POSIX_VDISABLE;
+        let keys = AsyncFd::new(fd)?;
+        let resized = signal(SignalKind::window_change())?;
+        *SAVED_TERMIOS.lock().unwrap_or_else(|err| err.into_inner()) = Some(termios);
+        PANIC_HOOK.call_once(|| {
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                restore();
+                previous(info);
+            }));
+        });
+        // SAFETY: raw is a valid termios copied from the terminal's own
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut tui = Tui {
+            stdout: io::stdout(),
+            rows: LinkedHashMap::new(),
+            keys,
+            redraw: time::interval(REDRAW_INTERVAL),
+            resized,
+        };
+        // Alternate screen, hidden cursor
+        write!(tui.stdout, "\x1b[?1049h\x1b[?25l")?;
+        tui.draw()?;
+        Ok(tui)
+    }
+
+    /// Shows a reading of a mouse
+    pub fn record(&mut self, reading: &Reading) {
+        let row = self
+            .rows
+            .entry(reading.name.to_owned())
+            .or_insert_with(|| Row {
+                model: reading.model.to_owned(),
+                state: reading.state,
+                time_remaining: None,
+                updated: Instant::now(),
+                recent: VecDeque::new(),
+            });
+        row.state = reading.state;
+        row.time_remaining = reading.time_remaining;
+        row.updated = Instant::now();
+        if let MouseState::Battery(status) = reading.state {
+            if row.recent.len() == SPARKLINE_LEN {
+                row.recent.pop_front();
+            }
+            row.recent.push_back(status.percent);
+        }
+        self.redraw();
+    }
+
+    /// Stops showing a mouse that went away
+    pub fn remove(&mut self, name: &str) {
+        if self.rows.remove(name).is_some() {
+            self.redraw();
+        }
+    }
+
+    /// Waits until `q` is pressed, keeping the screen up to date meanwhile
+    pub async fn quit(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.redraw.tick() => self.redraw(),
+                _ = self.resized.recv() => self.redraw(),
+                keys = read_keys(&self.keys) => match keys {
+                    Ok(keys) if keys.contains(&b'q') || keys.contains(&b'Q') => return,
+                    Ok(keys) if !keys.is_empty() => {}
+                    // Stdin was closed or can't be read, only a signal can stop us now
+                    Ok(_) | Err(_) => std::future::pending().await,
+                },
+            }
+        }
+    }
+
+    /// Draws the screen, giving up quietly since logging would draw over it
+    fn redraw(&mut self) {
+        let _ = self.draw();
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let (columns, lines) = size();
+        let mut screen = vec!["nezumi, press q to quit".to_owned(), String::new()];
+        if self.rows.is_empty() {
+            screen.push("Waiting for a mouse to connect".to_owned());
+        }
+        // The sparkline shows as many of the latest readings as fit
+        let history_len = columns.saturating_sub(HISTORY_LABEL.len());
+        for (name, row) in &self.rows {
+            screen.push(format!("{name} ({})", row.model));
+            let battery = match row.state {
+                MouseState::Battery(status) if status.is_charging => {
+                    format!("{}%, charging", status.percent)
+                }
+                MouseState::Battery(status) => match row.time_remaining {
+                    Some(remaining) => format!(
+                        "{}%, discharging, about {} min remaining",
+                        status.percent,
+                        format::minutes(remaining)
+                    ),
+                    None => format!("{}%, discharging", status.percent),
+                },
+                MouseState::Asleep => "off or asleep".to_owned(),
+                MouseState::NoResponse => "not responding".to_owned(),
+            };
+            screen.push(format!("  Battery  {battery}"));
+            screen.push(format!(
+                "  Updated  {}s ago",
+                row.updated.elapsed().as_secs()
+            ));
+            let skip = row.recent.len().saturating_sub(history_len);
+            screen.push(format!(
+                "{HISTORY_LABEL}{}",
+                sparkline(row.recent.iter().skip(skip).copied())
+            ));
+            screen.push(String::new());
+        }
+        let screen = fit(&screen, columns, lines);
+        write!(self.stdout, "\x1b[H\x1b[2J{screen}")?;
+        self.stdout.flush()
+    }
+}
+impl Drop for Tui {
+    /// Gives the terminal back the way it was
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Leaves the alternate screen and puts back the saved terminal settings, if not done already
+fn restore() {
+    let saved = SAVED_TERMIOS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .take();
+    let Some(termios) = saved else {
+        return;
+    };
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "\x1b[?25h\x1b[?1049l");
+    let _ = stdout.flush();
+    // SAFETY: the settings were read from this terminal when it was opened
+    unsafe {
+        libc::tcsetattr(io::stdin().as_raw_fd(), libc::TCSANOW, &termios);
+    }
+}
+
+/// Waits for key presses on stdin, returning nothing once it is closed
+async fn read_keys(stdin: &AsyncFd<RawFd>) -> io::Result<Vec<u8>> {
+    loop {
+        let mut guard = stdin.readable().await?;
+        let mut buf = [0; 64];
+        // SAFETY: the buffer is valid for its length. Stdin is readable, so with VMIN at 1 the
+        // read returns what was typed without blocking
+        let len = unsafe { libc::read(*stdin.get_ref(), buf.as_mut_ptr().cast(), buf.len()) };
+        let Ok(len) = usize::try_from(len) else {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        };
+        // The readiness is only waited for again once everything typed was read
+        if !has_input(*stdin.get_ref()) {
+            guard.clear_ready();
+        }
+        return Ok(buf[..len].to_vec());
+    }
+}
+
+/// Whether more input is waiting on a descriptor
+fn has_input(fd: RawFd) -> bool {
+    let mut poll_fd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: a single valid pollfd, polled without waiting
+    unsafe { libc::poll(&mut poll_fd, 1, 0) > 0 }
+}
+
+/// Columns and lines of the terminal on stdout
+fn size() -> (usize, usize) {
+    let mut winsize = MaybeUninit::<libc::winsize>::uninit();
+    // SAFETY: TIOCGWINSZ fills in the struct when it succeeds
+    let winsize = unsafe {
+        if libc::ioctl(
+            io::stdout().as_raw_fd(),
+            libc::TIOCGWINSZ,
+            winsize.as_mut_ptr(),
+        ) != 0
+        {
+            return DEFAULT_SIZE;
+        }
+        winsize.assume_init()
+    };
+    match (usize::from(winsize.ws_col), usize::from(winsize.ws_row)) {
+        (0, _) | (_, 0) => DEFAULT_SIZE,
+        size => size,
+    }
+}
+
+/// Joins the lines of the screen, cut to the width and height of the terminal
+fn fit(screen: &[String], columns: usize, lines: usize) -> String {
+    screen
+        .iter()
+        .take(lines)
+        .map(|line| line.chars().take(columns).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Draws battery percentages as a line of blocks, one per reading
+pub fn sparkline(percents: impl Iterator<Item = u16>) -> String {
+    percents
+        .map(|percent| BLOCKS[usize::from(percent.min(100)) * (BLOCKS.len() - 1) / 100])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparkline_blocks() {
+        assert_eq!(sparkline([].into_iter()), "");
+        assert_eq!(sparkline([0, 50, 100, 150].into_iter()), "▁▄██");
+    }
+
+    #[test]
+    fn fit_to_terminal() {
+        let screen = ["nezumi".to_owned(), "▁▄██▁▄".to_owned(), "cut".to_owned()];
+        assert_eq!(fit(&screen, 4, 2), "nezu\n▁▄██");
+        assert_eq!(fit(&screen, 80, 24), "nezumi\n▁▄██▁▄\ncut");
+    }
+}
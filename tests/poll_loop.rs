@@ -0,0 +1,188 @@
+// Copyright 2022 witchof0x20
+//
+// This file is part of nezumi.
+//
+// nezumi is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// nezumi is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with nezumi. If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs the polling loop of the daemon against mice answering with scripted responses
+use nezumi::config::MouseProfile;
+use nezumi::device::Node;
+use nezumi::format::{Format, Formatter, Icons};
+use nezumi::jitter::Jitter;
+use nezumi::mouse::mock::MockTransport;
+use nezumi::mouse::{aerox9, Mouse, MouseState};
+use nezumi::output::Output;
+use nezumi::poll::{poll_due, Events, OpenMouse, PollOptions, Schedule};
+use nezumi::template::Template;
+use nezumi::thresholds::Thresholds;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Captures everything written to an [`Output`]
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+impl Captured {
+    fn lines(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps track of what the loop reported
+#[derive(Default)]
+struct Recorded {
+    states: Vec<MouseState>,
+    read_errors: usize,
+    removed: Vec<String>,
+    /// Responses of the mouse each time it is reopened, after which it is gone
+    reopened: VecDeque<Vec<Vec<u8>>>,
+}
+impl Events for Recorded {
+    fn record(&mut self, _open_mouse: &mut OpenMouse<'_>, state: MouseState) {
+        self.states.push(state);
+    }
+
+    fn read_error(&mut self, _open_mouse: &OpenMouse<'_>, _err: &hidapi::HidError) {
+        self.read_errors += 1;
+    }
+
+    async fn reopen(&mut self, _open_mouse: &OpenMouse<'_>) -> Option<(Box<dyn Mouse>, Node)> {
+        let node = Node {
+            path: CString::new("/dev/hidraw0").unwrap(),
+            serial: None,
+        };
+        Some((wireless(self.reopened.pop_front()?), node))
+    }
+
+    fn removed(&mut self, open_mouse: &OpenMouse<'_>) {
+        self.removed.push(open_mouse.name.to_owned());
+    }
+}
+
+fn profile() -> MouseProfile {
+    toml::from_str(
+        "model = \"steelseries_aerox_9_wireless\"\n\
+         vendor = \"1038\"\nproduct = \"1858\"\nendpoint = 3",
+    )
+    .unwrap()
+}
+
+fn wireless(responses: Vec<Vec<u8>>) -> Box<dyn Mouse> {
+    Box::new(aerox9::Wireless::new(MockTransport::new(responses), 200))
+}
+
+fn formatter() -> Formatter {
+    Formatter::new(
+        Format::Percent,
+        Thresholds::default(),
+        Template::default(),
+        Icons::default(),
+        false,
+        Some("-1".to_owned()),
+        false,
+    )
+}
+
+const OPTIONS: PollOptions = PollOptions {
+    on_change: false,
+    read_retries: 0,
+    smooth: 1,
+    charging_reads: 1,
+    show_dpi: false,
+};
+
+/// Polls until every mouse is gone or the schedule runs out, the way the daemon does
+async fn run(
+    mice: &mut Vec<OpenMouse<'_>>,
+    schedule: &mut Schedule,
+    options: &PollOptions,
+    events: &mut Recorded,
+) -> String {
+    let captured = Captured::default();
+    let mut output = Output::Stream(BufWriter::new(Box::new(captured.clone())));
+    let formatter = formatter();
+    while !mice.is_empty() {
+        let printer = Some((&formatter, &mut output));
+        if poll_due(mice, schedule, printer, options, events)
+            .await
+            .is_break()
+        {
+            break;
+        }
+    }
+    captured.lines()
+}
+
+/// Every mouse is due on each pass
+fn every_pass(remaining: Option<u64>) -> Schedule {
+    Schedule {
+        interval: Duration::ZERO,
+        jitter: Jitter::default(),
+        remaining,
+    }
+}
+
+#[tokio::test]
+async fn reconnect() {
+    let profile = profile();
+    let responses = vec![
+        vec![0xd2, 0x0a],
+        // Unchanged, so nothing is printed with --on-change
+        vec![0xd2, 0x0a],
+        // A read that timed out
+        vec![],
+        vec![0xd2, 0x09],
+    ];
+    let mut mice = vec![OpenMouse::new("wireless", &profile, wireless(responses))];
+    // Once the responses run out the mouse is reopened, and it starts charging
+    let mut events = Recorded {
+        reopened: VecDeque::from([vec![vec![0xd2, 0x89]]]),
+        ..Recorded::default()
+    };
+    let options = PollOptions {
+        on_change: true,
+        ..OPTIONS
+    };
+    let lines = run(&mut mice, &mut every_pass(None), &options, &mut events).await;
+    assert_eq!(lines, "45\n-1\n40\n40\n");
+    assert_eq!(events.states.len(), 5);
+    assert_eq!(events.read_errors, 2);
+    assert_eq!(events.removed, ["wireless"]);
+    assert!(mice.is_empty());
+}
+
+#[tokio::test]
+async fn max_iterations() {
+    let profile = profile();
+    let responses = vec![
+        vec![0xd2, 0x0a],
+        // Only battery readings count
+        vec![],
+        vec![0xd2, 0x09],
+        vec![0xd2, 0x08],
+        vec![0xd2, 0x07],
+    ];
+    let mut mice = vec![OpenMouse::new("wireless", &profile, wireless(responses))];
+    let mut events = Recorded::default();
+    let mut schedule = every_pass(Some(3));
+    let lines = run(&mut mice, &mut schedule, &OPTIONS, &mut events).await;
+    assert_eq!(lines, "45\n-1\n40\n35\n");
+    assert_eq!(schedule.remaining, Some(0));
+    assert_eq!(events.read_errors, 0);
+    // The mouse is still there, polling just stopped
+    assert_eq!(mice.len(), 1);
+}